@@ -0,0 +1,239 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Generate the known search providers and their provider files from `providers.toml`.
+//!
+//! Write the provider definitions to `$OUT_DIR/providers.rs`, and a provider file
+//! for every desktop ID to `$OUT_DIR/providers/`, and to `$PROVIDER_FILES_DIR` if set.
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// The manifest of all known providers.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Manifest {
+    bus_name: String,
+    object_path_prefix: String,
+    providers: Vec<Provider>,
+}
+
+/// A single provider in the manifest.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Provider {
+    label: String,
+    desktop_ids: Vec<String>,
+    #[serde(default)]
+    executables: Vec<String>,
+    object_path: String,
+    dirnames: Vec<String>,
+    argv_dirname: String,
+    bases: Vec<String>,
+}
+
+/// Convert a kind of installation in the manifest to a `ConfigBase` expression.
+fn config_base(base: &str) -> String {
+    match base.split_once(':') {
+        None if base == "xdg" => "ConfigBase::Xdg".to_string(),
+        None if base == "portable" => "ConfigBase::Portable".to_string(),
+        Some(("flatpak", app_id)) => format!("ConfigBase::Flatpak({:?})", app_id),
+        Some(("snap", name)) => format!("ConfigBase::Snap({:?})", name),
+        _ => panic!("Invalid kind of installation: {}", base),
+    }
+}
+
+/// Check that the manifest has no duplicate or invalid desktop IDs and object paths.
+fn validate(manifest: &Manifest) {
+    let mut desktop_ids = HashSet::new();
+    let mut object_paths = HashSet::new();
+    let mut executables = HashSet::new();
+    for provider in &manifest.providers {
+        assert!(
+            !provider.desktop_ids.is_empty(),
+            "No desktop IDs for provider {}",
+            provider.label
+        );
+        for desktop_id in &provider.desktop_ids {
+            assert!(
+                desktop_id.ends_with(".desktop"),
+                "Desktop ID {} of provider {} must end with .desktop",
+                desktop_id,
+                provider.label
+            );
+            assert!(
+                desktop_ids.insert(desktop_id),
+                "Duplicate desktop ID {}",
+                desktop_id
+            );
+        }
+        for executable in &provider.executables {
+            assert!(
+                executables.insert(executable),
+                "Duplicate executable {}",
+                executable
+            );
+        }
+        assert!(
+            provider.object_path.split('/').all(|segment| {
+                !segment.is_empty()
+                    && segment
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            }),
+            "Invalid object path {} of provider {}",
+            provider.object_path,
+            provider.label
+        );
+        assert!(
+            object_paths.insert(&provider.object_path),
+            "Duplicate object path {}",
+            provider.object_path
+        );
+        assert!(
+            !provider.dirnames.is_empty(),
+            "No configuration directory names for provider {}",
+            provider.label
+        );
+        assert!(
+            !provider.bases.is_empty(),
+            "No kinds of installations for provider {}",
+            provider.label
+        );
+    }
+}
+
+/// Generate Rust code for the given `manifest`.
+fn generate_rust(manifest: &Manifest) -> String {
+    let mut code = String::new();
+    writeln!(
+        code,
+        "// Generated by build.rs from providers.toml; do not edit."
+    )
+    .unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "/// The default name to request on the bus.").unwrap();
+    writeln!(code, "const BUSNAME: &str = {:?};", manifest.bus_name).unwrap();
+    writeln!(code).unwrap();
+    writeln!(
+        code,
+        "/// The default prefix for object paths of search providers."
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "const OBJPATH_PREFIX: &str = {:?};",
+        manifest.object_path_prefix
+    )
+    .unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "/// Known search providers, from `providers.toml`.").unwrap();
+    writeln!(code, "const PROVIDERS: &[ProviderDefinition] = &[").unwrap();
+    for provider in &manifest.providers {
+        writeln!(code, "    ProviderDefinition {{").unwrap();
+        writeln!(code, "        label: {:?},", provider.label).unwrap();
+        writeln!(code, "        desktop_ids: &{:?},", provider.desktop_ids).unwrap();
+        writeln!(code, "        executables: &{:?},", provider.executables).unwrap();
+        writeln!(
+            code,
+            "        relative_obj_path: {:?},",
+            provider.object_path
+        )
+        .unwrap();
+        writeln!(code, "        config: ConfigLocation {{").unwrap();
+        writeln!(code, "            dirnames: &{:?},", provider.dirnames).unwrap();
+        writeln!(
+            code,
+            "            argv_dirname: {:?},",
+            provider.argv_dirname
+        )
+        .unwrap();
+        let bases = provider
+            .bases
+            .iter()
+            .map(|base| config_base(base))
+            .collect::<Vec<_>>();
+        writeln!(code, "            bases: &[{}],", bases.join(", ")).unwrap();
+        writeln!(code, "        }},").unwrap();
+        writeln!(code, "    }},").unwrap();
+    }
+    writeln!(code, "];").unwrap();
+    code
+}
+
+/// Generate the provider files for the given `manifest`.
+///
+/// Return the file name and the contents of every provider file.
+fn generate_provider_files(manifest: &Manifest) -> Vec<(String, String)> {
+    manifest
+        .providers
+        .iter()
+        .flat_map(|provider| {
+            provider.desktop_ids.iter().map(move |desktop_id| {
+                let name = format!(
+                    "{}.{}.ini",
+                    manifest.bus_name.to_lowercase(),
+                    desktop_id.trim_end_matches(".desktop")
+                );
+                let contents = format!(
+                    "[Shell Search Provider]\nDesktopId={}\nBusName={}\nObjectPath={}/{}\nVersion=2\n",
+                    desktop_id, manifest.bus_name, manifest.object_path_prefix, provider.object_path
+                );
+                (name, contents)
+            })
+        })
+        .collect()
+}
+
+/// Write all provider `files` to `directory`, and remove stale provider files.
+///
+/// Only remove provider files for `bus_name`, to keep provider files of other
+/// applications if `directory` is shared.
+fn write_provider_files(directory: &Path, bus_name: &str, files: &[(String, String)]) {
+    std::fs::create_dir_all(directory).unwrap();
+    let prefix = format!("{}.", bus_name.to_lowercase());
+    for entry in std::fs::read_dir(directory).unwrap() {
+        let path = entry.unwrap().path();
+        let is_stale = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| {
+                name.starts_with(&prefix) && name.ends_with(".ini")
+            });
+        if is_stale {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+    for (name, contents) in files {
+        std::fs::write(directory.join(name), contents).unwrap();
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=providers.toml");
+    println!("cargo:rerun-if-env-changed=PROVIDER_FILES_DIR");
+
+    let manifest: Manifest = toml::from_str(
+        &std::fs::read_to_string("providers.toml").expect("Failed to read providers.toml"),
+    )
+    .expect("Invalid providers.toml");
+    validate(&manifest);
+
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+    std::fs::write(out_dir.join("providers.rs"), generate_rust(&manifest)).unwrap();
+
+    let files = generate_provider_files(&manifest);
+    write_provider_files(&out_dir.join("providers"), &manifest.bus_name, &files);
+    if let Some(directory) = std::env::var_os("PROVIDER_FILES_DIR") {
+        // Rerun if the directory disappears
+        println!("cargo:rerun-if-changed={}", Path::new(&directory).display());
+        write_provider_files(Path::new(&directory), &manifest.bus_name, &files);
+    }
+}
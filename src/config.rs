@@ -0,0 +1,798 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The configuration file.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use tracing::{debug, error, info};
+use url::Url;
+
+use gnome_search_provider_common::gio::glib;
+
+use crate::template::DescriptionTemplate;
+
+/// Settings for search results.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ResultsConfig {
+    /// Whether to return a placeholder result if there are no recent workspaces at all.
+    pub placeholder_when_empty: bool,
+    /// Whether to show recently opened files along with workspaces.
+    pub include_recent_files: bool,
+    /// Return at most this many results per provider; 0 returns all results.
+    pub max_results: usize,
+    /// Discard results which score less than this.
+    pub min_score: f64,
+    /// Whether to match the project names in manifests of local workspaces.
+    pub match_project_names: bool,
+    /// Workspaces to rank above all other matching workspaces.
+    ///
+    /// Either URLs, or paths of local workspaces; see [`ResultsConfig::pinned_urls`].
+    pub pinned: Vec<String>,
+    /// The template for descriptions of results; empty for the default description.
+    ///
+    /// See [`DescriptionTemplate::parse`].
+    pub description: String,
+}
+
+impl Default for ResultsConfig {
+    fn default() -> Self {
+        Self {
+            placeholder_when_empty: false,
+            include_recent_files: false,
+            max_results: 10,
+            min_score: 0.0,
+            match_project_names: false,
+            pinned: Vec::new(),
+            description: String::new(),
+        }
+    }
+}
+
+impl ResultsConfig {
+    /// The maximum number of results per provider, or `None` to return all results.
+    pub fn max_results(&self) -> Option<usize> {
+        Some(self.max_results).filter(|max| *max != 0)
+    }
+
+    /// The URLs of all pinned workspaces.
+    ///
+    /// Take entries with `://` as URLs, and all other entries as paths of local
+    /// workspaces; relative paths and paths starting with `~/` are relative to `home`.
+    pub fn pinned_urls(&self, home: &Path) -> Vec<String> {
+        self.pinned
+            .iter()
+            .filter_map(|pinned| {
+                if pinned.contains("://") {
+                    Some(pinned.clone())
+                } else {
+                    let path = home.join(pinned.strip_prefix("~/").unwrap_or(pinned));
+                    // Only fails for relative paths
+                    Url::from_file_path(path).ok().map(String::from)
+                }
+            })
+            .collect()
+    }
+
+    /// Parse the `description` template, or return `None` if it's empty.
+    pub fn description_template(&self) -> Result<Option<DescriptionTemplate>> {
+        if self.description.is_empty() {
+            Ok(None)
+        } else {
+            DescriptionTemplate::parse(&self.description)
+                .context("Invalid description template")
+                .map(Some)
+        }
+    }
+}
+
+/// Settings for filtering workspaces.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FilterConfig {
+    /// Whether to hide local workspaces which no longer exist.
+    pub hide_missing_workspaces: bool,
+    /// Glob patterns of workspaces to hide.
+    ///
+    /// `~/` at the start of a pattern refers to the home directory.
+    pub exclude: Vec<String>,
+}
+
+impl FilterConfig {
+    /// Compile the `exclude` patterns, expanding `~/` to `home`.
+    ///
+    /// `*` doesn't match `/`, but `**` does, e.g. `**/node_modules/**` matches every
+    /// workspace within a `node_modules` directory.
+    pub fn exclude_set(&self, home: &Path) -> Result<GlobSet> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &self.exclude {
+            let expanded = match pattern.strip_prefix("~/") {
+                Some(rest) => format!("{}/{}", home.display(), rest),
+                None => pattern.clone(),
+            };
+            let glob = GlobBuilder::new(&expanded)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("Invalid exclude pattern {}", pattern))?;
+            builder.add(glob);
+        }
+        builder.build().map_err(Into::into)
+    }
+}
+
+/// Settings for reading the storage of apps.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct StorageConfig {
+    /// Skip storage larger than this many MiB; 0 reads storage of any size.
+    pub max_size_mib: u64,
+    /// Extra directories to look for configuration directories of apps in.
+    ///
+    /// Relative paths are relative to the home directory.
+    pub extra_config_roots: Vec<PathBuf>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            max_size_mib: 64,
+            extra_config_roots: Vec::new(),
+        }
+    }
+}
+
+impl StorageConfig {
+    /// The maximum size of storage in bytes, or `None` if storage may have any size.
+    pub fn max_size(&self) -> Option<u64> {
+        Some(self.max_size_mib)
+            .filter(|size| *size != 0)
+            .map(|size| size.saturating_mul(1024 * 1024))
+    }
+}
+
+/// Settings for a single provider.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ProviderConfig {
+    /// Whether to serve this provider at all.
+    pub enabled: bool,
+    /// The configuration directory of the app, instead of the default one.
+    ///
+    /// Relative paths are relative to the home directory.
+    pub config_dir: Option<PathBuf>,
+}
+
+impl Default for ProviderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            config_dir: None,
+        }
+    }
+}
+
+/// A search provider for an app which this service doesn't know of.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CustomProviderConfig {
+    /// A human readable label for the provider.
+    pub label: String,
+    /// The ID of the desktop file of the app.
+    pub desktop_id: String,
+    /// The configuration directory of the app.
+    ///
+    /// Relative paths are relative to the home directory.
+    pub config_dir: PathBuf,
+    /// The object path of the provider, relative to the object path prefix of this service.
+    pub object_path: String,
+    /// The URL of a VSCode server, e.g. code-server, to open workspaces at.
+    ///
+    /// If set, open workspaces at this server with the app, e.g. a browser,
+    /// instead of opening them in the app directly.
+    #[serde(default)]
+    pub server_url: Option<String>,
+}
+
+impl CustomProviderConfig {
+    /// Check that this provider has a valid desktop ID, object path and server URL.
+    pub fn validate(&self) -> Result<()> {
+        if !self.desktop_id.ends_with(".desktop") {
+            return Err(anyhow!(
+                "desktop-id {} of custom provider {} must end with .desktop",
+                self.desktop_id,
+                self.label
+            ));
+        }
+        if !is_valid_relative_object_path(&self.object_path) {
+            return Err(anyhow!(
+                "object-path {} of custom provider {} must consist of ASCII letters, digits and underscores, separated by single slashes",
+                self.object_path,
+                self.label
+            ));
+        }
+        if let Some(server_url) = &self.server_url {
+            match Url::parse(server_url) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+                _ => {
+                    return Err(anyhow!(
+                        "server-url {} of custom provider {} must be an http or https URL",
+                        server_url,
+                        self.label
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Definitions of custom providers in a drop-in file.
+///
+/// Packagers can ship drop-in files to add providers for their builds of VSCode.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProviderDropIn {
+    /// The providers in this file.
+    pub providers: Vec<CustomProviderConfig>,
+}
+
+impl ProviderDropIn {
+    /// The directories to load drop-in files from, in increasing order of precedence.
+    pub fn default_dirs() -> Vec<PathBuf> {
+        glib::system_data_dirs()
+            .into_iter()
+            .rev()
+            .chain(std::iter::once(glib::user_data_dir()))
+            .map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("providers.d"))
+            .collect()
+    }
+
+    /// Parse a drop-in file from `data`.
+    pub fn parse(data: &str) -> Result<Self> {
+        toml::from_str(data).map_err(Into::into)
+    }
+
+    /// Load the providers of all `*.toml` files in `dirs`.
+    ///
+    /// Load files in order of their names; a file replaces files of the same name
+    /// in preceding directories.  Ignore directories which don't exist.
+    ///
+    /// Skip files which can't be read or parsed, invalid providers, and providers
+    /// whose object path is `reserved` or already used by a preceding provider, and
+    /// log an error for each, so that a broken drop-in can't prevent the service from
+    /// starting.
+    pub fn load_all(dirs: &[PathBuf], reserved: &[&str]) -> Vec<CustomProviderConfig> {
+        let mut files = BTreeMap::new();
+        for dir in dirs {
+            let entries = match std::fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(error) if error.kind() == ErrorKind::NotFound => {
+                    debug!("{} not found, skipping", dir.display());
+                    continue;
+                }
+                Err(error) => {
+                    error!("Failed to read directory {}: {}", dir.display(), error);
+                    continue;
+                }
+            };
+            for entry in entries {
+                let path = match entry {
+                    Ok(entry) => entry.path(),
+                    Err(error) => {
+                        error!("Failed to read directory {}: {}", dir.display(), error);
+                        continue;
+                    }
+                };
+                if let (Some(name), Some("toml")) = (
+                    path.file_name(),
+                    path.extension().and_then(|ext| ext.to_str()),
+                ) {
+                    files.insert(name.to_owned(), path);
+                }
+            }
+        }
+        let mut providers: Vec<CustomProviderConfig> = Vec::new();
+        for path in files.values() {
+            info!("Loading provider definitions from {}", path.display());
+            let drop_in = std::fs::read_to_string(path)
+                .with_context(|| {
+                    format!(
+                        "Failed to read provider definitions from {}",
+                        path.display()
+                    )
+                })
+                .and_then(|data| {
+                    Self::parse(&data).with_context(|| {
+                        format!("Invalid provider definitions in {}", path.display())
+                    })
+                });
+            let drop_in = match drop_in {
+                Ok(drop_in) => drop_in,
+                Err(error) => {
+                    error!("{:#}; skipping", error);
+                    continue;
+                }
+            };
+            for provider in drop_in.providers {
+                let used = reserved.contains(&provider.object_path.as_str())
+                    || providers
+                        .iter()
+                        .any(|other| other.object_path == provider.object_path);
+                let result = provider.validate().and_then(|_| {
+                    if used {
+                        Err(anyhow!(
+                            "object-path {} of custom provider {} is already used by another provider",
+                            provider.object_path,
+                            provider.label
+                        ))
+                    } else {
+                        Ok(())
+                    }
+                });
+                match result {
+                    Ok(_) => providers.push(provider),
+                    Err(error) => error!("{} in {}; skipping", error, path.display()),
+                }
+            }
+        }
+        providers
+    }
+}
+
+/// Whether `path` is a valid relative DBus object path.
+fn is_valid_relative_object_path(path: &str) -> bool {
+    path.split('/').all(|segment| {
+        !segment.is_empty()
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// The configuration of this service.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Settings for search results.
+    pub results: ResultsConfig,
+    /// Settings for filtering workspaces.
+    pub filter: FilterConfig,
+    /// Settings for reading the storage of apps.
+    pub storage: StorageConfig,
+    /// Settings for individual providers, by provider key.
+    pub providers: BTreeMap<String, ProviderConfig>,
+    /// Search providers for apps which this service doesn't know of.
+    #[serde(rename = "custom-providers")]
+    pub custom_providers: Vec<CustomProviderConfig>,
+}
+
+impl Config {
+    /// The default location of the configuration file.
+    pub fn default_path() -> PathBuf {
+        glib::user_config_dir()
+            .join(env!("CARGO_PKG_NAME"))
+            .join("config.toml")
+    }
+
+    /// Parse a configuration from `data`.
+    pub fn parse(data: &str) -> Result<Self> {
+        toml::from_str(data).map_err(Into::into)
+    }
+
+    /// Load the configuration file at `path`.
+    ///
+    /// Return the default configuration if `path` doesn't exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(data) => {
+                info!("Loading configuration from {}", path.display());
+                Self::parse(&data)
+                    .with_context(|| format!("Invalid configuration in {}", path.display()))
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                debug!("{} not found, using default configuration", path.display());
+                Ok(Self::default())
+            }
+            Err(error) => Err(error)
+                .with_context(|| format!("Failed to read configuration from {}", path.display())),
+        }
+    }
+
+    /// Check that all provider sections refer to one of the `known` provider keys.
+    pub fn validate_providers(&self, known: &[&str]) -> Result<()> {
+        match self
+            .providers
+            .keys()
+            .find(|key| !known.contains(&key.as_str()))
+        {
+            Some(key) => Err(anyhow!(
+                "Unknown provider {} in section [providers.{}], expected one of: {}",
+                key,
+                key,
+                known.join(", ")
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that all custom providers have valid desktop IDs and object paths.
+    ///
+    /// Object paths must be unique, and differ from all `reserved` object paths.
+    pub fn validate_custom_providers(&self, reserved: &[&str]) -> Result<()> {
+        let mut paths = HashSet::new();
+        for provider in &self.custom_providers {
+            provider.validate()?;
+            if reserved.contains(&provider.object_path.as_str())
+                || !paths.insert(provider.object_path.as_str())
+            {
+                return Err(anyhow!(
+                    "object-path {} of custom provider {} is already used by another provider",
+                    provider.object_path,
+                    provider.label
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the settings for the provider with the given `key`.
+    pub fn provider(&self, key: &str) -> Option<&ProviderConfig> {
+        self.providers.get(key)
+    }
+
+    /// Whether the provider with the given `key` is enabled.
+    pub fn is_enabled(&self, key: &str) -> bool {
+        self.provider(key).map_or(true, |provider| provider.enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_empty() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn parse_all_sections() {
+        let config = Config::parse(
+            r#"
+[results]
+placeholder-when-empty = true
+include-recent-files = true
+match-project-names = true
+
+[filter]
+hide-missing-workspaces = true
+"#,
+        )
+        .unwrap();
+        assert!(config.results.placeholder_when_empty);
+        assert!(config.results.include_recent_files);
+        assert!(config.results.match_project_names);
+        assert!(config.filter.hide_missing_workspaces);
+    }
+
+    #[test]
+    fn results_max_results() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.results.max_results(), Some(10));
+        let config = Config::parse("[results]\nmax-results = 5\n").unwrap();
+        assert_eq!(config.results.max_results(), Some(5));
+        let config = Config::parse("[results]\nmax-results = 0\n").unwrap();
+        assert_eq!(config.results.max_results(), None);
+        assert!(Config::parse("[results]\nmax-results = -1\n").is_err());
+    }
+
+    #[test]
+    fn results_min_score() {
+        assert_eq!(Config::parse("").unwrap().results.min_score, 0.0);
+        let config = Config::parse("[results]\nmin-score = 1.5\n").unwrap();
+        assert_eq!(config.results.min_score, 1.5);
+        let config = Config::parse("[results]\nmin-score = 1\n").unwrap();
+        assert_eq!(config.results.min_score, 1.0);
+    }
+
+    #[test]
+    fn results_description_template() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.results.description_template().unwrap(), None);
+        let config = Config::parse("[results]\ndescription = \"{path} ({kind})\"\n").unwrap();
+        assert_eq!(
+            config.results.description_template().unwrap(),
+            Some(DescriptionTemplate::parse("{path} ({kind})").unwrap())
+        );
+        let config = Config::parse("[results]\ndescription = \"{name}\"\n").unwrap();
+        assert!(config.results.description_template().is_err());
+    }
+
+    #[test]
+    fn results_pinned_urls() {
+        let config = Config::parse(
+            r#"
+[results]
+pinned = ["vscode-remote://ssh-remote%2Bfoo/srv/app", "/home/foo/dev/mdcat", "~/dev/my app", "dev/other"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.results.pinned_urls(Path::new("/home/foo")),
+            vec![
+                "vscode-remote://ssh-remote%2Bfoo/srv/app",
+                "file:///home/foo/dev/mdcat",
+                "file:///home/foo/dev/my%20app",
+                "file:///home/foo/dev/other",
+            ]
+        );
+        assert!(Config::parse("").unwrap().results.pinned.is_empty());
+    }
+
+    #[test]
+    fn filter_exclude_set() {
+        let config = Config::parse(
+            r#"
+[filter]
+exclude = ["**/node_modules/**", "/tmp/**", "~/scratch/*"]
+"#,
+        )
+        .unwrap();
+        let exclude = config.filter.exclude_set(Path::new("/home/foo")).unwrap();
+        assert!(exclude.is_match("/home/foo/dev/app/node_modules/left-pad"));
+        assert!(exclude.is_match("/tmp/foo/mdcat"));
+        assert!(exclude.is_match("/home/foo/scratch/mdcat"));
+        assert!(!exclude.is_match("/home/foo/scratch/mdcat/src"));
+        assert!(!exclude.is_match("/home/foo/dev/mdcat"));
+        assert!(Config::parse("").unwrap().filter.exclude.is_empty());
+        let config = Config::parse("[filter]\nexclude = [\"[\"]\n").unwrap();
+        assert!(config.filter.exclude_set(Path::new("/home/foo")).is_err());
+    }
+
+    #[test]
+    fn storage_max_size() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.storage.max_size(), Some(64 * 1024 * 1024));
+        let config = Config::parse("[storage]\nmax-size-mib = 2\n").unwrap();
+        assert_eq!(config.storage.max_size(), Some(2 * 1024 * 1024));
+        let config = Config::parse("[storage]\nmax-size-mib = 0\n").unwrap();
+        assert_eq!(config.storage.max_size(), None);
+        assert!(Config::parse("[storage]\nmax-size-mib = -1\n").is_err());
+    }
+
+    #[test]
+    fn parse_extra_config_roots() {
+        let config = Config::parse(
+            "[storage]\nextra-config-roots = [\"/mnt/home/.config\", \".old-config\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.storage.extra_config_roots,
+            vec![
+                PathBuf::from("/mnt/home/.config"),
+                PathBuf::from(".old-config")
+            ]
+        );
+        assert!(Config::parse("[storage]\nextra-config-roots = \"/mnt\"\n").is_err());
+    }
+
+    #[test]
+    fn parse_provider_config_dir() {
+        let config = Config::parse(
+            r#"
+[providers.code]
+config-dir = ".local/share/Code"
+
+[providers.codium]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.provider("code").unwrap().config_dir,
+            Some(PathBuf::from(".local/share/Code"))
+        );
+        assert_eq!(config.provider("codium").unwrap().config_dir, None);
+        assert!(config.provider("code-oss").is_none());
+    }
+
+    #[test]
+    fn parse_provider_enabled() {
+        let config = Config::parse(
+            r#"
+[providers.code]
+enabled = false
+
+[providers.codium]
+config-dir = ".config/VSCodium"
+"#,
+        )
+        .unwrap();
+        assert!(!config.is_enabled("code"));
+        assert!(config.is_enabled("codium"));
+        assert!(config.is_enabled("code-oss"));
+    }
+
+    #[test]
+    fn parse_custom_providers() {
+        let config = Config::parse(
+            r#"
+[[custom-providers]]
+label = "My Code"
+desktop-id = "my-code.desktop"
+config-dir = ".config/My Code"
+object-path = "custom/mycode"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.custom_providers,
+            vec![CustomProviderConfig {
+                label: "My Code".to_string(),
+                desktop_id: "my-code.desktop".to_string(),
+                config_dir: PathBuf::from(".config/My Code"),
+                object_path: "custom/mycode".to_string(),
+                server_url: None,
+            }]
+        );
+        assert!(config.validate_custom_providers(&["codium"]).is_ok());
+        assert!(Config::parse("[[custom-providers]]\nlabel = \"My Code\"\n").is_err());
+    }
+
+    #[test]
+    fn validate_custom_providers() {
+        let provider = |desktop_id: &str, object_path: &str| CustomProviderConfig {
+            label: "My Code".to_string(),
+            desktop_id: desktop_id.to_string(),
+            config_dir: PathBuf::from(".config/My Code"),
+            object_path: object_path.to_string(),
+            server_url: None,
+        };
+        let validate = |providers: Vec<CustomProviderConfig>| {
+            Config {
+                custom_providers: providers,
+                ..Config::default()
+            }
+            .validate_custom_providers(&["codium"])
+            .map_err(|error| error.to_string())
+        };
+        assert_eq!(
+            validate(vec![provider("my-code", "mycode")]).unwrap_err(),
+            "desktop-id my-code of custom provider My Code must end with .desktop"
+        );
+        for path in ["", "/mycode", "my-code", "custom//mycode", "custom/"] {
+            assert!(
+                validate(vec![provider("my-code.desktop", path)])
+                    .unwrap_err()
+                    .contains("must consist of ASCII letters"),
+                "{}",
+                path
+            );
+        }
+        assert_eq!(
+            validate(vec![provider("my-code.desktop", "codium")]).unwrap_err(),
+            "object-path codium of custom provider My Code is already used by another provider"
+        );
+        assert!(validate(vec![
+            provider("my-code.desktop", "custom/mycode"),
+            provider("other-code.desktop", "custom/mycode")
+        ])
+        .is_err());
+        assert!(validate(vec![
+            provider("my-code.desktop", "custom/mycode"),
+            provider("other-code.desktop", "custom/other_code")
+        ])
+        .is_ok());
+    }
+
+    #[test]
+    fn validate_custom_provider_server_url() {
+        let config = |server_url: &str| {
+            Config::parse(&format!(
+                r#"
+[[custom-providers]]
+label = "code-server"
+desktop-id = "firefox.desktop"
+config-dir = ".local/share/code-server"
+object-path = "server/codeserver"
+server-url = "{}"
+"#,
+                server_url
+            ))
+            .unwrap()
+        };
+        let valid = config("http://localhost:8080");
+        assert_eq!(
+            valid.custom_providers[0].server_url.as_deref(),
+            Some("http://localhost:8080")
+        );
+        assert!(valid.validate_custom_providers(&[]).is_ok());
+        assert!(config("https://code.example.com/")
+            .validate_custom_providers(&[])
+            .is_ok());
+        for invalid in ["localhost:8080", "file:///home/foo", "not a url"] {
+            assert_eq!(
+                config(invalid)
+                    .validate_custom_providers(&[])
+                    .unwrap_err()
+                    .to_string(),
+                format!(
+                    "server-url {} of custom provider code-server must be an http or https URL",
+                    invalid
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn load_provider_drop_ins() {
+        let drop_ins = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests")
+            .join("drop-ins");
+        let providers = ProviderDropIn::load_all(
+            &[
+                drop_ins.join("system"),
+                drop_ins.join("does-not-exist"),
+                drop_ins.join("user"),
+            ],
+            &["codium"],
+        );
+        assert_eq!(
+            providers
+                .iter()
+                .map(|provider| (provider.label.as_str(), provider.object_path.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Fork Code (local)", "fork/code"),
+                ("Other Code", "other/code")
+            ]
+        );
+        assert_eq!(
+            providers[0].config_dir,
+            PathBuf::from(".local/share/Fork Code")
+        );
+        assert!(ProviderDropIn::load_all(&[], &[]).is_empty());
+        assert!(ProviderDropIn::parse("[[providers]]\nlabel = \"Fork\"\n").is_err());
+    }
+
+    #[test]
+    fn validate_unknown_provider() {
+        let config = Config::parse("[providers.vscode]\nconfig-dir = \"/tmp\"\n").unwrap();
+        let message = config
+            .validate_providers(&["code", "codium"])
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            message,
+            "Unknown provider vscode in section [providers.vscode], expected one of: code, codium"
+        );
+        assert!(config.validate_providers(&["vscode"]).is_ok());
+    }
+
+    #[test]
+    fn parse_unknown_key() {
+        let error = Config::parse("[results]\nplaceholder = true\n").unwrap_err();
+        let message = format!("{:#}", error);
+        assert!(
+            message.contains("unknown field `placeholder`"),
+            "{}",
+            message
+        );
+        assert!(message.contains("line 2"), "{}", message);
+    }
+
+    #[test]
+    fn load_missing_file() {
+        let config = Config::load(Path::new("/does/not/exist/config.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+}
@@ -0,0 +1,443 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Remember how often and how recently workspaces were opened, and for which searches.
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+
+use gnome_search_provider_common::gio::glib;
+
+/// The maximum number of workspaces to remember.
+const MAX_WORKSPACES: usize = 500;
+
+/// The maximum number of searches to remember chosen workspaces for.
+const MAX_QUERIES: usize = 500;
+
+/// The maximum number of characters of a search to remember.
+const MAX_QUERY_LENGTH: usize = 256;
+
+/// The maximum bonus to add to the score of workspaces chosen for similar searches.
+///
+/// Like [`MAX_FRECENCY_BONUS`] less than the score of a name match.
+pub const MAX_LEARNED_BONUS: f64 = 3.0;
+
+/// The maximum bonus to add to the score of frequently and recently opened workspaces.
+///
+/// Less than the score of a name match, so that a frequently opened workspace doesn't
+/// rank above workspaces which match the terms much better.
+pub const MAX_FRECENCY_BONUS: f64 = 3.0;
+
+/// How often and when a workspace was opened.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Visits {
+    /// How often the workspace was opened.
+    count: u32,
+    /// When the workspace was last opened, in seconds since the epoch.
+    last_visit: u64,
+}
+
+impl Visits {
+    /// The frecency of these visits at `now`, in seconds since the epoch.
+    ///
+    /// Weigh the number of visits by the age of the last visit, like Firefox does for
+    /// its location bar.
+    fn frecency(&self, now: u64) -> f64 {
+        let days = now.saturating_sub(self.last_visit) / (24 * 60 * 60);
+        let weight = match days {
+            0..=3 => 1.0,
+            4..=13 => 0.7,
+            14..=30 => 0.5,
+            31..=90 => 0.3,
+            _ => 0.1,
+        };
+        self.count as f64 * weight
+    }
+}
+
+/// The workspaces chosen for a search.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Choices {
+    /// How often each workspace was chosen, by workspace URL.
+    workspaces: HashMap<String, u32>,
+    /// When a workspace was last chosen for the search, in seconds since the epoch.
+    last_choice: u64,
+}
+
+/// Convert search `terms` to a query to remember chosen workspaces for.
+///
+/// Join the lowercase terms with spaces, and take at most [`MAX_QUERY_LENGTH`]
+/// characters.
+fn query<S: AsRef<str>>(terms: &[S]) -> String {
+    terms
+        .iter()
+        .map(|term| term.as_ref().to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(MAX_QUERY_LENGTH)
+        .collect()
+}
+
+/// How similar the queries `a` and `b` are.
+///
+/// Equal queries have a similarity of 1, and queries which start with the other query,
+/// e.g. `md` and `mdcat`, have a similarity of ½; other queries aren't similar at all.
+fn similarity(a: &str, b: &str) -> f64 {
+    if a == b {
+        1.0
+    } else if a.starts_with(b) || b.starts_with(a) {
+        0.5
+    } else {
+        0.0
+    }
+}
+
+/// The history of opened workspaces.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+struct History {
+    /// Visits of workspaces, by workspace URL.
+    workspaces: HashMap<String, Visits>,
+    /// Workspaces chosen for searches, by query.
+    queries: HashMap<String, Choices>,
+}
+
+impl History {
+    /// Record a visit of the workspace at `url` at `now`.
+    ///
+    /// Forget the workspaces with the least frecency if the history has more than
+    /// [`MAX_WORKSPACES`].
+    fn visit(&mut self, url: &str, now: u64) {
+        let visits = self.workspaces.entry(url.to_string()).or_insert(Visits {
+            count: 0,
+            last_visit: now,
+        });
+        visits.count = visits.count.saturating_add(1);
+        visits.last_visit = now;
+        if MAX_WORKSPACES < self.workspaces.len() {
+            let mut frecencies = self
+                .workspaces
+                .iter()
+                .map(|(url, visits)| (visits.frecency(now), url.clone()))
+                .collect::<Vec<_>>();
+            frecencies.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+            for (_, url) in frecencies.into_iter().skip(MAX_WORKSPACES) {
+                self.workspaces.remove(&url);
+            }
+        }
+    }
+
+    /// Record that the workspace at `url` was chosen for `query` at `now`.
+    ///
+    /// Ignore empty queries.  Forget the searches whose workspaces were chosen least
+    /// recently if the history has more than [`MAX_QUERIES`].
+    fn choose(&mut self, query: &str, url: &str, now: u64) {
+        if query.is_empty() {
+            return;
+        }
+        let choices = self
+            .queries
+            .entry(query.to_string())
+            .or_insert_with(|| Choices {
+                workspaces: HashMap::new(),
+                last_choice: now,
+            });
+        let count = choices.workspaces.entry(url.to_string()).or_insert(0);
+        *count = count.saturating_add(1);
+        choices.last_choice = now;
+        if MAX_QUERIES < self.queries.len() {
+            let mut last_choices = self
+                .queries
+                .iter()
+                .map(|(query, choices)| (choices.last_choice, query.clone()))
+                .collect::<Vec<_>>();
+            last_choices.sort_by(|(a, _), (b, _)| b.cmp(a));
+            for (_, query) in last_choices.into_iter().skip(MAX_QUERIES) {
+                self.queries.remove(&query);
+            }
+        }
+    }
+
+    /// The bonus of every workspace in this history at `now` for `query`, by URL.
+    ///
+    /// Add the frecency bonus of every workspace, and the bonus for choosing a workspace
+    /// for searches similar to `query`; see [`similarity`].  Both bonuses grow with
+    /// frecency and number of choices respectively, up to [`MAX_FRECENCY_BONUS`] and
+    /// [`MAX_LEARNED_BONUS`].
+    fn bonuses(&self, now: u64, query: &str) -> HashMap<String, f64> {
+        let mut bonuses = self
+            .workspaces
+            .iter()
+            .map(|(url, visits)| {
+                let frecency = visits.frecency(now);
+                (
+                    url.clone(),
+                    MAX_FRECENCY_BONUS * frecency / (frecency + 5.0),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let mut choices: HashMap<&str, f64> = HashMap::new();
+        if !query.is_empty() {
+            for (chosen_for, chosen) in &self.queries {
+                let similarity = similarity(query, chosen_for);
+                if 0.0 < similarity {
+                    for (url, count) in &chosen.workspaces {
+                        *choices.entry(url).or_insert(0.0) += similarity * *count as f64;
+                    }
+                }
+            }
+        }
+        for (url, choices) in choices {
+            *bonuses.entry(url.to_string()).or_insert(0.0) +=
+                MAX_LEARNED_BONUS * choices / (choices + 2.0);
+        }
+        bonuses
+    }
+}
+
+/// The current time in seconds since the epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// Frequency and recency of opened workspaces.
+///
+/// Clones share the same history.
+#[derive(Debug, Clone, Default)]
+pub struct Frecency {
+    /// The file to persist the history in, if any.
+    path: Option<PathBuf>,
+    history: Arc<Mutex<History>>,
+}
+
+impl Frecency {
+    /// The default file to persist the history in.
+    ///
+    /// glib doesn't know `$XDG_STATE_HOME` yet, so look at the environment directly.
+    pub fn default_path() -> PathBuf {
+        std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .unwrap_or_else(|| glib::home_dir().join(".local").join("state"))
+            .join(env!("CARGO_PKG_NAME"))
+            .join("frecency.json")
+    }
+
+    /// Load the history persisted at `path`, and persist the history to `path` afterwards.
+    ///
+    /// Start with an empty history if `path` doesn't exist or has an invalid history.
+    pub fn load(path: PathBuf) -> Self {
+        let history = match std::fs::read_to_string(&path) {
+            Ok(data) => {
+                info!(
+                    "Loading history of opened workspaces from {}",
+                    path.display()
+                );
+                serde_json::from_str(&data).unwrap_or_else(|error| {
+                    error!("Invalid history in {}: {}", path.display(), error);
+                    History::default()
+                })
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                debug!("{} not found, starting with empty history", path.display());
+                History::default()
+            }
+            Err(error) => {
+                error!("Failed to read history from {}: {}", path.display(), error);
+                History::default()
+            }
+        };
+        Self {
+            path: Some(path),
+            history: Arc::new(Mutex::new(history)),
+        }
+    }
+
+    /// Record that the workspace at `url` was opened right now from a search for `terms`,
+    /// and persist the history.
+    ///
+    /// Persist the history from a task on the main loop, to not hold up the caller with
+    /// writing the history.
+    pub fn visit<S: AsRef<str>>(&self, url: &str, terms: &[S]) {
+        {
+            let mut history = self.history.lock().unwrap();
+            let now = now();
+            history.visit(url, now);
+            history.choose(&query(terms), url, now);
+        }
+        if let Some(path) = self.path.clone() {
+            let history = self.history.clone();
+            glib::MainContext::default().spawn(async move {
+                if let Err(error) = save(&path, &history) {
+                    error!("{:#}", error);
+                }
+            });
+        }
+    }
+
+    /// The bonus of every opened workspace right now for a search for `terms`, by URL.
+    ///
+    /// The bonus grows with the frecency of a workspace, and with how often it was
+    /// chosen for similar searches.
+    pub fn bonuses<S: AsRef<str>>(&self, terms: &[S]) -> HashMap<String, f64> {
+        self.history.lock().unwrap().bonuses(now(), &query(terms))
+    }
+
+    /// The seconds since the workspace at `url` was last opened from search, if ever.
+    pub fn time_since_visit(&self, url: &str) -> Option<u64> {
+        let history = self.history.lock().unwrap();
+        let visits = history.workspaces.get(url)?;
+        Some(now().saturating_sub(visits.last_visit))
+    }
+}
+
+/// Write `history` to `path`.
+///
+/// Only hold the lock on `history` while serializing it.  Write to a temporary file
+/// first, and then move it over `path`, to never leave a partial history behind.
+fn save(path: &Path, history: &Mutex<History>) -> Result<()> {
+    let data = serde_json::to_string(&*history.lock().unwrap())?;
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Failed to create {}", directory.display()))?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, data)
+        .and_then(|_| std::fs::rename(&temp_path, path))
+        .with_context(|| format!("Failed to write history to {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::PoisonError;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn frecency_decays_with_age() {
+        let visits = Visits {
+            count: 10,
+            last_visit: 100 * DAY,
+        };
+        assert_eq!(visits.frecency(100 * DAY), 10.0);
+        assert_eq!(visits.frecency(110 * DAY), 7.0);
+        assert_eq!(visits.frecency(120 * DAY), 5.0);
+        assert_eq!(visits.frecency(150 * DAY), 3.0);
+        assert_eq!(visits.frecency(200 * DAY), 1.0);
+    }
+
+    #[test]
+    fn history_bonuses_prefer_frequent_and_recent_visits() {
+        let mut history = History::default();
+        history.visit("file:///home/foo/old", 0);
+        history.visit("file:///home/foo/old", 0);
+        history.visit("file:///home/foo/recent", 100 * DAY);
+        history.visit("file:///home/foo/frequent", 100 * DAY);
+        history.visit("file:///home/foo/frequent", 100 * DAY);
+        let bonuses = history.bonuses(100 * DAY, "");
+        let old = bonuses["file:///home/foo/old"];
+        let recent = bonuses["file:///home/foo/recent"];
+        let frequent = bonuses["file:///home/foo/frequent"];
+        assert!(0.0 < old, "{}", old);
+        assert!(old < recent, "{} < {}", old, recent);
+        assert!(recent < frequent, "{} < {}", recent, frequent);
+        assert!(frequent < MAX_FRECENCY_BONUS, "{}", frequent);
+    }
+
+    #[test]
+    fn history_forgets_least_frecent_workspaces() {
+        let mut history = History::default();
+        history.visit("file:///home/foo/old", 0);
+        for index in 0..MAX_WORKSPACES {
+            history.visit(&format!("file:///home/foo/{}", index), 100 * DAY);
+        }
+        assert_eq!(history.workspaces.len(), MAX_WORKSPACES);
+        assert!(!history.workspaces.contains_key("file:///home/foo/old"));
+    }
+
+    #[test]
+    fn history_bonuses_prefer_workspaces_chosen_for_similar_queries() {
+        let mut history = History::default();
+        history.choose("mdcat", "file:///home/foo/mdcat", 0);
+        history.choose("mdcat", "file:///home/foo/mdcat", 0);
+        history.choose("md", "file:///home/foo/mdcat-fork", 0);
+        history.choose("other", "file:///home/foo/other", 0);
+        history.choose("", "file:///home/foo/empty", 0);
+        let bonuses = history.bonuses(0, "mdcat");
+        let exact = bonuses["file:///home/foo/mdcat"];
+        let similar = bonuses["file:///home/foo/mdcat-fork"];
+        assert!(0.0 < similar, "{}", similar);
+        assert!(similar < exact, "{} < {}", similar, exact);
+        assert!(exact < MAX_LEARNED_BONUS, "{}", exact);
+        assert!(!bonuses.contains_key("file:///home/foo/other"));
+        assert!(!bonuses.contains_key("file:///home/foo/empty"));
+        assert!(history.bonuses(0, "").is_empty());
+    }
+
+    #[test]
+    fn history_forgets_least_recently_chosen_queries() {
+        let mut history = History::default();
+        history.choose("old", "file:///home/foo/old", 0);
+        for index in 0..MAX_QUERIES {
+            history.choose(&index.to_string(), "file:///home/foo/new", DAY);
+        }
+        assert_eq!(history.queries.len(), MAX_QUERIES);
+        assert!(!history.queries.contains_key("old"));
+    }
+
+    #[test]
+    fn query_joins_lowercase_terms() {
+        assert_eq!(query(&["MDCat", "Dev"]), "mdcat dev");
+        assert_eq!(
+            query(&["a".repeat(MAX_QUERY_LENGTH + 1)]),
+            "a".repeat(MAX_QUERY_LENGTH)
+        );
+    }
+
+    #[test]
+    fn load_saved_history() {
+        let _main_context = crate::tests::MAIN_CONTEXT
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let path = std::env::temp_dir()
+            .join(format!(
+                "gnome-search-providers-vscode-test-frecency-{}",
+                std::process::id()
+            ))
+            .join("frecency.json");
+        let frecency = Frecency::load(path.clone());
+        assert!(frecency.bonuses(&["mdcat"]).is_empty());
+        frecency.visit("file:///home/foo/mdcat", &["mdcat"]);
+        // Run the task which saves the history
+        let context = glib::MainContext::default();
+        while context.iteration(false) {}
+        let loaded = Frecency::load(path.clone());
+        assert_eq!(
+            loaded.bonuses(&["other"]).keys().collect::<Vec<_>>(),
+            vec!["file:///home/foo/mdcat"]
+        );
+        assert!(
+            loaded.bonuses(&["other"])["file:///home/foo/mdcat"]
+                < loaded.bonuses(&["mdcat"])["file:///home/foo/mdcat"]
+        );
+        assert!(loaded.time_since_visit("file:///home/foo/mdcat").unwrap() < 60);
+        assert_eq!(loaded.time_since_visit("file:///home/foo/other"), None);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
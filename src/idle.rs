@@ -0,0 +1,86 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Quit the service when idle.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{error, info};
+
+use gnome_search_provider_common::gio::glib;
+
+/// Track the last activity of the service.
+///
+/// Clones share the same activity.
+#[derive(Debug, Clone)]
+pub struct ActivityTracker(Arc<Mutex<Instant>>);
+
+impl ActivityTracker {
+    /// Create a new tracker, with the last activity right now.
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    /// Record activity right now.
+    pub fn touch(&self) {
+        *self.0.lock().unwrap() = Instant::now();
+    }
+
+    /// How long the service has been idle.
+    pub fn idle_time(&self) -> Duration {
+        self.0.lock().unwrap().elapsed()
+    }
+}
+
+impl Default for ActivityTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wait until `activity` was idle for `timeout`, then release `busname` and quit `main_loop`.
+///
+/// After we released the name the bus activates the service again on the next search.
+pub async fn quit_when_idle(
+    connection: zbus::Connection,
+    busname: String,
+    activity: ActivityTracker,
+    timeout: Duration,
+    main_loop: glib::MainLoop,
+) {
+    loop {
+        let idle_time = activity.idle_time();
+        if timeout <= idle_time {
+            break;
+        }
+        glib::timeout_future(timeout - idle_time).await;
+    }
+    info!(
+        "Idle for {}s, releasing {} and quitting",
+        timeout.as_secs(),
+        busname
+    );
+    if let Err(error) = connection.release_name(busname.as_str()).await {
+        error!("Failed to release name {}: {:#}", busname, error);
+    }
+    main_loop.quit();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ActivityTracker;
+    use std::time::Duration;
+
+    #[test]
+    fn touch_resets_idle_time() {
+        let activity = ActivityTracker::new();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(Duration::from_millis(20) <= activity.idle_time());
+        activity.clone().touch();
+        assert!(activity.idle_time() < Duration::from_millis(20));
+    }
+}
@@ -8,25 +8,84 @@
 
 //! Gnome search provider for VSCode editors.
 
-use std::io::Read;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{File, Permissions};
+use std::io::{ErrorKind, Read};
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, Context, Error, Result};
-use async_trait::async_trait;
-use serde::Deserialize;
-use tracing::{debug, error, info, instrument, trace, warn, Span};
-use tracing_futures::Instrument;
+use indexmap::IndexMap;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use tracing::{debug, error, info, instrument, trace, warn};
 
 use gnome_search_provider_common::app::*;
-use gnome_search_provider_common::futures_channel;
 use gnome_search_provider_common::gio;
 use gnome_search_provider_common::gio::glib;
 use gnome_search_provider_common::gio::prelude::*;
 use gnome_search_provider_common::logging::*;
 use gnome_search_provider_common::mainloop::*;
-use gnome_search_provider_common::matching::*;
-use gnome_search_provider_common::source::{AsyncItemsSource, IdMap};
-use gnome_search_provider_common::zbus;
+
+mod config;
+mod frecency;
+mod idle;
+mod manifest;
+mod searchprovider;
+mod template;
+
+use config::{Config, ProviderDropIn};
+use frecency::Frecency;
+use idle::{quit_when_idle, ActivityTracker};
+use searchprovider::{
+    add_workspace, ProviderApp, ProviderSettings, StorageChanges, VscodeSearchProvider,
+};
+
+/// Deserialize an optional value, and ignore it if it has an unexpected shape.
+fn lenient<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(value.and_then(|value| {
+        serde_json::from_value(value)
+            .map_err(|error| warn!("Ignoring unexpected value: {}", error))
+            .ok()
+    }))
+}
+
+/// Deserialize an optional list, and skip all items with an unexpected shape.
+///
+/// Ignore the whole value if it's no list.
+fn lenient_list<'de, D, T>(deserializer: D) -> std::result::Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    match Option::<serde_json::Value>::deserialize(deserializer)? {
+        Some(serde_json::Value::Array(items)) => Ok(Some(
+            items
+                .into_iter()
+                .filter_map(|item| {
+                    serde_json::from_value(item)
+                        .map_err(|error| warn!("Skipping unexpected list item: {}", error))
+                        .ok()
+                })
+                .collect(),
+        )),
+        Some(_) => {
+            warn!("Ignoring unexpected value instead of list");
+            Ok(None)
+        }
+        None => Ok(None),
+    }
+}
 
 #[derive(Debug, Deserialize)]
 struct WorkspaceEntry {
@@ -39,86 +98,958 @@ struct WorkspaceEntry {
 enum StorageOpenedPathsListEntry {
     Workspace {
         workspace: WorkspaceEntry,
+        #[serde(default, deserialize_with = "lenient")]
+        label: Option<String>,
     },
     Folder {
         #[serde(rename = "folderUri")]
         uri: String,
+        #[serde(default, deserialize_with = "lenient")]
+        label: Option<String>,
+    },
+    File {
+        #[serde(rename = "fileUri")]
+        uri: String,
+        #[serde(default, deserialize_with = "lenient")]
+        label: Option<String>,
     },
     Other(serde_json::Value),
 }
 
 impl StorageOpenedPathsListEntry {
-    /// Move this entry into a workspace URL.
-    fn into_workspace_url(self) -> Option<String> {
-        match self {
-            Self::Workspace { workspace } => Some(workspace.config_path),
-            Self::Folder { uri } => Some(uri),
-            Self::Other(_) => None,
-        }
+    /// Move this entry into a recent entry.
+    fn into_recent_entry(self) -> Option<RecentEntry> {
+        let (url, label) = match self {
+            Self::Workspace { workspace, label } => {
+                (RecentUrl::Workspace(workspace.config_path), label)
+            }
+            Self::Folder { uri, label } => (RecentUrl::Workspace(uri), label),
+            Self::File { uri, label } => (RecentUrl::File(uri), label),
+            Self::Other(value) => {
+                debug!("Skipping unexpected entry {}", value);
+                return None;
+            }
+        };
+        Some(RecentEntry { url, label })
+    }
+}
+
+/// A recently opened path.
+#[derive(Debug, Clone, PartialEq)]
+struct RecentEntry {
+    /// The URL of the path.
+    url: RecentUrl,
+    /// A label the user gave the path, e.g. for a remote workspace.
+    label: Option<String>,
+}
+
+impl From<RecentUrl> for RecentEntry {
+    fn from(url: RecentUrl) -> Self {
+        Self { url, label: None }
     }
 }
 
+/// The URL of a recently opened path.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum RecentUrl {
+    /// A folder or a multi-root workspace.
+    Workspace(String),
+    /// A single file.
+    File(String),
+}
+
 #[derive(Debug, Deserialize)]
 struct StorageOpenedPathsList {
     /// Up to code 1.54
+    #[serde(default, deserialize_with = "lenient_list")]
     workspaces3: Option<Vec<String>>,
     /// From code 1.55
+    #[serde(default, deserialize_with = "lenient_list")]
     entries: Option<Vec<StorageOpenedPathsListEntry>>,
 }
 
+/// The relevant parts of `workspace.json` in a workspace storage directory.
+#[derive(Debug, Deserialize)]
+struct WorkspaceStorageEntry {
+    /// The URL of an opened folder.
+    folder: Option<String>,
+    /// The URL of an opened workspace file.
+    workspace: Option<String>,
+}
+
+impl WorkspaceStorageEntry {
+    /// Move this entry into a workspace URL.
+    fn into_workspace_url(self) -> Option<String> {
+        self.folder.or(self.workspace)
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct Storage {
-    #[serde(rename = "openedPathsList")]
+    #[serde(rename = "openedPathsList", default, deserialize_with = "lenient")]
     opened_paths_list: Option<StorageOpenedPathsList>,
 }
 
+/// How often to try to parse `storage.json` before giving up.
+const STORAGE_READ_ATTEMPTS: u32 = 3;
+
+/// How long to wait before parsing `storage.json` again for the first time.
+///
+/// We double the delay for every further attempt.
+const STORAGE_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Fail if `size` of the storage at `path` exceeds `max_size`.
+fn check_size(path: &Path, size: u64, max_size: u64) -> Result<()> {
+    if max_size < size {
+        Err(anyhow!(
+            "Storage in {} has {} bytes, more than the maximum of {} bytes",
+            path.display(),
+            size,
+            max_size
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// How long to wait for VSCode to release a lock on its state database.
+const STATE_DB_BUSY_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Whether `error` was caused by a locked SQLite database.
+fn is_database_locked(error: &Error) -> bool {
+    error.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<rusqlite::Error>(),
+            Some(rusqlite::Error::SqliteFailure(error, _))
+                if matches!(
+                    error.code,
+                    rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+                )
+        )
+    })
+}
+
+/// Whether `error` was caused by missing permissions to read a file.
+fn is_permission_denied(error: &Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .map_or(false, |error| error.kind() == ErrorKind::PermissionDenied)
+            || cause.downcast_ref::<glib::Error>().map_or(false, |error| {
+                error.matches(gio::IOErrorEnum::PermissionDenied)
+            })
+    })
+}
+
+/// Add context about reading storage data from `path` to `error`.
+///
+/// If we may not read `path` tell the user how to fix the permissions of `path`.
+fn storage_read_error(error: Error, path: &Path) -> Error {
+    if is_permission_denied(&error) {
+        error.context(format!(
+            "Not allowed to read storage data from {}; make sure the file is owned by and readable for user {}",
+            path.display(),
+            glib::user_name().to_string_lossy()
+        ))
+    } else {
+        error.context(format!(
+            "Failed to read storage data from {}",
+            path.display()
+        ))
+    }
+}
+
+/// Whether `error` comes from JSON data which ends prematurely, e.g. a partially written file.
+fn is_truncated(error: &Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<serde_json::Error>()
+            .map_or(false, serde_json::Error::is_eof)
+    })
+}
+
+/// Strip comments and trailing commas from JSONC `data`.
+///
+/// VSCode never writes comments to its storage, but users who edit the storage
+/// by hand sometimes add some.
+fn strip_jsonc(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut in_string = false;
+    let mut index = 0;
+    while index < data.len() {
+        let byte = data[index];
+        if in_string {
+            output.push(byte);
+            match byte {
+                b'\\' => {
+                    if let Some(escaped) = data.get(index + 1) {
+                        output.push(*escaped);
+                        index += 1;
+                    }
+                }
+                b'"' => in_string = false,
+                _ => {}
+            }
+            index += 1;
+            continue;
+        }
+        match (byte, data.get(index + 1)) {
+            (b'/', Some(b'/')) => {
+                while index < data.len() && data[index] != b'\n' {
+                    index += 1;
+                }
+            }
+            (b'/', Some(b'*')) => {
+                index += 2;
+                while index < data.len() && !data[index..].starts_with(b"*/") {
+                    index += 1;
+                }
+                index += 2;
+                output.push(b' ');
+            }
+            (b'}' | b']', _) => {
+                if let Some(last) = output.iter().rposition(|b| !b.is_ascii_whitespace()) {
+                    if output[last] == b',' {
+                        output.remove(last);
+                    }
+                }
+                output.push(byte);
+                index += 1;
+            }
+            _ => {
+                in_string = byte == b'"';
+                output.push(byte);
+                index += 1;
+            }
+        }
+    }
+    output
+}
+
+/// The header of every SQLite database file.
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
 impl Storage {
+    /// Read recent workspaces from the storage file at `path`.
+    ///
+    /// Read `path` as global state database if it's a SQLite database, e.g.
+    /// `state.vscdb`, and as `storage.json` otherwise.
+    fn from_file(path: &Path) -> Result<Self> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        if data.starts_with(SQLITE_HEADER) {
+            Self::from_state_db(path, None)
+        } else {
+            Self::read(data.as_slice())
+        }
+    }
+
     /// Read a VSCode storage.json from the given `reader`.
-    fn read<R: Read>(reader: R) -> Result<Self> {
-        serde_json::from_reader(reader).map_err(Into::into)
+    ///
+    /// Tolerate comments and trailing commas in the storage.
+    fn read<R: Read>(mut reader: R) -> Result<Self> {
+        // Read all data first, to keep IO errors apart from parse errors
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        serde_json::from_slice(&strip_jsonc(&data)).map_err(Into::into)
     }
 
-    /// Read the `storage.json` file in the given `config_dir`.
-    #[instrument]
-    async fn from_dir<P: AsRef<Path> + std::fmt::Debug>(config_dir: P) -> Result<Self> {
-        let path = config_dir.as_ref().join("storage.json");
+    /// Read recent workspaces from VSCode's global state database at `path`.
+    ///
+    /// Since 1.64 VSCode keeps the list of recently opened paths in `state.vscdb`, a
+    /// SQLite database, and no longer writes it to `storage.json`.
+    ///
+    /// Open the database read-only.  If VSCode keeps the database locked for too long
+    /// read a snapshot of the database instead.
+    ///
+    /// Fail if the list of recently opened paths has more than `max_size` bytes.
+    fn from_state_db(path: &Path, max_size: Option<u64>) -> Result<Self> {
+        // SQLite doesn't tell why it can't open a database, so check whether we may
+        // read the database at all first.
+        File::open(path).map_err(|error| storage_read_error(error.into(), path))?;
+        match Self::query_state_db(path, OpenFlags::SQLITE_OPEN_READ_ONLY, max_size) {
+            Err(error) if is_database_locked(&error) => {
+                debug!("{:#}; reading snapshot of database instead", error);
+                Self::from_state_db_snapshot(path, max_size)
+            }
+            result => result,
+        }
+    }
+
+    /// Read recent workspaces from a snapshot of the global state database at `path`.
+    ///
+    /// Copy the database along with its write-ahead log to a new private directory in
+    /// the user's runtime directory and query the copy, to never block or wait for
+    /// VSCode.
+    fn from_state_db_snapshot(path: &Path, max_size: Option<u64>) -> Result<Self> {
+        let read_snapshot = || -> Result<Self> {
+            // Create a fresh directory with a random name, and make it private before
+            // copying anything into it.
+            let snapshot_dir = tempfile::Builder::new()
+                .prefix(concat!(env!("CARGO_PKG_NAME"), "-"))
+                .tempdir_in(glib::user_runtime_dir())
+                .context("Failed to create directory for snapshot")?;
+            std::fs::set_permissions(snapshot_dir.path(), Permissions::from_mode(0o700))?;
+            let snapshot = snapshot_dir.path().join("state.vscdb");
+            std::fs::copy(path, &snapshot)?;
+            let mut wal = path.as_os_str().to_owned();
+            wal.push("-wal");
+            let wal = PathBuf::from(wal);
+            if wal.is_file() {
+                std::fs::copy(&wal, snapshot_dir.path().join("state.vscdb-wal"))?;
+            }
+            let result =
+                Self::query_state_db(&snapshot, OpenFlags::SQLITE_OPEN_READ_WRITE, max_size);
+            let dir = snapshot_dir.path().to_path_buf();
+            if let Err(error) = snapshot_dir.close() {
+                warn!("Failed to remove {}: {}", dir.display(), error);
+            }
+            result
+        };
+        read_snapshot().with_context(|| {
+            format!(
+                "Failed to read snapshot of state database {}",
+                path.display()
+            )
+        })
+    }
+
+    /// Query recent workspaces from the global state database at `path`, opened with `flags`.
+    ///
+    /// Fail if the list of recently opened paths has more than `max_size` bytes.
+    fn query_state_db(path: &Path, flags: OpenFlags, max_size: Option<u64>) -> Result<Self> {
+        let connection = Connection::open_with_flags(path, flags)
+            .with_context(|| format!("Failed to open state database {}", path.display()))?;
+        connection
+            .busy_timeout(STATE_DB_BUSY_TIMEOUT)
+            .with_context(|| format!("Failed to configure state database {}", path.display()))?;
+        if let Some(max_size) = max_size {
+            // Check the size first, to avoid loading the whole list into memory
+            let size: Option<i64> = connection
+                .query_row(
+                    "SELECT length(CAST(value AS BLOB)) FROM ItemTable WHERE key = ?1",
+                    ["history.recentlyOpenedPathsList"],
+                    |row| row.get(0),
+                )
+                .optional()
+                .with_context(|| format!("Failed to query state database {}", path.display()))?;
+            check_size(path, size.unwrap_or(0).max(0) as u64, max_size)?;
+        }
+        let value: Option<String> = connection
+            .query_row(
+                "SELECT CAST(value AS TEXT) FROM ItemTable WHERE key = ?1",
+                ["history.recentlyOpenedPathsList"],
+                |row| row.get(0),
+            )
+            .optional()
+            .with_context(|| format!("Failed to query state database {}", path.display()))?;
+        let opened_paths_list = value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .with_context(|| {
+                format!(
+                    "Failed to parse recently opened paths from {}",
+                    path.display()
+                )
+            })?;
+        Ok(Self { opened_paths_list })
+    }
+
+    /// Read the `storage.json` file at `path`.
+    ///
+    /// VSCode sometimes rewrites `storage.json` while we read it, so retry a few
+    /// times with increasing delays if the data ends prematurely.  Fail right away
+    /// if the data doesn't parse otherwise.
+    ///
+    /// Fail if the file has more than `max_size` bytes.
+    async fn from_storage_json(path: &Path, max_size: Option<u64>) -> Result<Self> {
+        let mut delay = STORAGE_RETRY_DELAY;
+        let mut attempt = 1;
+        loop {
+            if let Some(max_size) = max_size {
+                // Check the size first, to avoid loading a huge file into memory
+                if let Ok(metadata) = std::fs::metadata(path) {
+                    check_size(path, metadata.len(), max_size)?;
+                }
+            }
+            let data = Self::load_storage_json(path).await?;
+            if let Some(max_size) = max_size {
+                // The file may have grown since we checked its size
+                check_size(path, data.len() as u64, max_size)?;
+            }
+            match Self::read(data.as_slice()) {
+                Ok(storage) => return Ok(storage),
+                Err(error) if attempt < STORAGE_READ_ATTEMPTS && is_truncated(&error) => {
+                    debug!(
+                        "Failed to parse storage from {} in attempt {}, retrying in {}ms: {:#}",
+                        path.display(),
+                        attempt,
+                        delay.as_millis(),
+                        error
+                    );
+                    glib::timeout_future(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(error) => {
+                    return Err(error).with_context(|| {
+                        format!("Failed to parse storage from {}", path.display())
+                    })
+                }
+            }
+        }
+    }
+
+    /// Load the raw contents of the `storage.json` file at `path`.
+    async fn load_storage_json(path: &Path) -> Result<Vec<u8>> {
         trace!("Reading storage from {}", path.display());
         let (data, _) = gio::File::for_path(&path)
             .load_contents_async_future()
             .await
-            .with_context(|| format!("Failed to read storage data from {}", path.display()))?;
-        Self::read(data.as_slice())
-            .with_context(|| format!("Failed to parse storage from {}", path.display()))
+            .map_err(|error| storage_read_error(error.into(), path))?;
+        Ok(data)
     }
 
-    /// Move this storage into workspace URLs.
-    fn into_workspace_urls(self) -> Vec<String> {
-        trace!("Extracting workspace URLs from {:?}", self);
+    /// Whether this storage has no recently opened paths at all.
+    fn is_empty(&self) -> bool {
+        self.opened_paths_list.as_ref().map_or(true, |paths| {
+            paths.entries.as_ref().map_or(true, Vec::is_empty)
+                && paths.workspaces3.as_ref().map_or(true, Vec::is_empty)
+        })
+    }
+
+    /// Move this storage into recently opened workspaces and files.
+    fn into_recent_entries(self) -> Vec<RecentEntry> {
+        trace!("Extracting recent entries from {:?}", self);
         if let Some(paths) = self.opened_paths_list {
             let entries = paths.entries.unwrap_or_default();
             let workspaces3 = paths.workspaces3.unwrap_or_default();
             entries
                 .into_iter()
-                .filter_map(|entry| entry.into_workspace_url())
-                .chain(workspaces3.into_iter())
+                .filter_map(|entry| entry.into_recent_entry())
+                .chain(
+                    workspaces3
+                        .into_iter()
+                        .map(|url| RecentUrl::Workspace(url).into()),
+                )
                 .collect()
         } else {
             Vec::new()
         }
     }
+
+    /// Move this storage into workspace URLs.
+    fn into_workspace_urls(self) -> Vec<String> {
+        self.into_recent_entries()
+            .into_iter()
+            .filter_map(|entry| match entry.url {
+                RecentUrl::Workspace(url) => Some(url),
+                RecentUrl::File(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// A source of recent workspaces in the configuration directory of an app.
+#[derive(Debug)]
+struct WorkspaceSource {
+    /// The file this source was read from.
+    path: PathBuf,
+    /// When the file was last modified, if known.
+    modified: Option<SystemTime>,
+    /// The recent workspaces in this source.
+    storage: Storage,
 }
 
+impl WorkspaceSource {
+    /// Find all global state databases in `config_dir`.
+    ///
+    /// Return the database of the default profile first, followed by the databases
+    /// of all other profiles in `User/profiles/`, ordered by profile ID.
+    fn state_db_paths(config_dir: &Path) -> Vec<PathBuf> {
+        Self::global_storage_dirs(config_dir)
+            .into_iter()
+            .map(|dir| dir.join("state.vscdb"))
+            .filter(|path| path.is_file())
+            .collect()
+    }
+
+    /// Find the global storage directories of all profiles in `config_dir`.
+    ///
+    /// Return the directory of the default profile first, followed by the directories
+    /// of all other profiles in `User/profiles/`, ordered by profile ID.
+    fn global_storage_dirs(config_dir: &Path) -> Vec<PathBuf> {
+        let user_dir = config_dir.join("User");
+        let mut profiles = match std::fs::read_dir(user_dir.join("profiles")) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .collect::<Vec<_>>(),
+            Err(error) => {
+                debug!("No profiles in {}: {}", user_dir.display(), error);
+                Vec::new()
+            }
+        };
+        profiles.sort();
+        std::iter::once(user_dir)
+            .chain(profiles)
+            .map(|dir| dir.join("globalStorage"))
+            .collect()
+    }
+
+    /// Stamp all sources of recent workspaces in `config_dir`.
+    ///
+    /// The stamps change whenever any source changes, including the write-ahead
+    /// log of a state database.  Missing sources have no stamp.
+    fn stamps(config_dir: &Path) -> Vec<(PathBuf, FileStamp)> {
+        [
+            config_dir.join("storage.json"),
+            config_dir.join("storage.json.backup"),
+        ]
+        .into_iter()
+        .chain(
+            Self::global_storage_dirs(config_dir)
+                .into_iter()
+                .flat_map(|dir| [dir.join("state.vscdb"), dir.join("state.vscdb-wal")]),
+        )
+        .chain(std::iter::once(
+            config_dir.join("User").join("workspaceStorage"),
+        ))
+        .map(|path| {
+            let stamp = std::fs::metadata(&path)
+                .and_then(|metadata| Ok((metadata.modified()?, metadata.len())))
+                .ok();
+            (path, stamp)
+        })
+        .collect()
+    }
+
+    /// Find all directories with sources of recent workspaces in `config_dir`.
+    ///
+    /// Return every directory along with the prefix of the names of the relevant
+    /// files in the directory, or `None` if all files in the directory are relevant.
+    fn source_dirs(config_dir: &Path) -> Vec<(PathBuf, Option<&'static str>)> {
+        let user_dir = config_dir.join("User");
+        std::iter::once((config_dir.to_path_buf(), Some("storage.json")))
+            .chain(
+                Self::global_storage_dirs(config_dir)
+                    .into_iter()
+                    .map(|dir| (dir, Some("state.vscdb"))),
+            )
+            .chain([
+                (user_dir.join("profiles"), None),
+                (user_dir.join("workspaceStorage"), None),
+            ])
+            .collect()
+    }
+
+    /// Read all sources of recent workspaces in `config_dir`.
+    ///
+    /// Read `storage.json` and the global state databases of all profiles, if any.
+    /// If `storage.json` can't be read, e.g. because it's corrupt, read the backup
+    /// `storage.json.backup` instead.
+    /// Only fail if no source could be read at all, with the error of `storage.json`,
+    /// or if we may not read a state database.
+    ///
+    /// Skip sources with more than `max_size` bytes.
+    #[instrument]
+    async fn read_all(config_dir: PathBuf, max_size: Option<u64>) -> Result<Vec<Self>> {
+        let mut sources = Vec::new();
+        for state_db in Self::state_db_paths(&config_dir) {
+            trace!("Reading state database {}", state_db.display());
+            match Storage::from_state_db(&state_db, max_size) {
+                Ok(storage) => sources.push(Self::new(state_db, storage)),
+                // Don't silently fall back to outdated workspaces from storage.json
+                // if the user needs to fix the permissions of the database.
+                Err(error) if is_permission_denied(&error) => return Err(error),
+                Err(error) => warn!("{:#}", error),
+            }
+        }
+        let storage_json = config_dir.join("storage.json");
+        let storage_error = match Storage::from_storage_json(&storage_json, max_size).await {
+            Ok(storage) => {
+                sources.push(Self::new(storage_json, storage));
+                None
+            }
+            Err(error) => {
+                let backup = config_dir.join("storage.json.backup");
+                if backup.is_file() {
+                    match Storage::from_storage_json(&backup, max_size).await {
+                        Ok(storage) => {
+                            warn!("{:#}; using {} instead", error, backup.display());
+                            sources.push(Self::new(backup, storage));
+                            None
+                        }
+                        Err(backup_error) => {
+                            debug!("{:#}", backup_error);
+                            Some(error)
+                        }
+                    }
+                } else {
+                    Some(error)
+                }
+            }
+        };
+        if sources.iter().all(|source| source.storage.is_empty()) {
+            if let Some(source) = Self::from_workspace_storage(&config_dir) {
+                info!(
+                    "No recent workspaces in {}, using workspaces from {}",
+                    config_dir.display(),
+                    source.path.display()
+                );
+                sources.push(source);
+            }
+        }
+        match storage_error {
+            Some(error) if sources.is_empty() => Err(error),
+            Some(error) => {
+                debug!("Ignoring storage.json: {:#}", error);
+                Ok(sources)
+            }
+            None => Ok(sources),
+        }
+    }
+
+    /// Recover workspaces from the workspace storage directories in `config_dir`.
+    ///
+    /// VSCode keeps a directory with a `workspace.json` for every workspace it ever
+    /// opened in `User/workspaceStorage`; use these if the list of recently opened
+    /// paths is missing, e.g. after an upgrade wiped `storage.json`.  Order
+    /// workspaces by the modification time of their `workspace.json`, newest first.
+    ///
+    /// Return `None` if there are no workspace storage directories.
+    fn from_workspace_storage(config_dir: &Path) -> Option<Self> {
+        let path = config_dir.join("User").join("workspaceStorage");
+        let mut workspaces = std::fs::read_dir(&path)
+            .map_err(|error| debug!("Failed to read {}: {}", path.display(), error))
+            .ok()?
+            .filter_map(|entry| {
+                let file = entry.ok()?.path().join("workspace.json");
+                let modified = std::fs::metadata(&file)
+                    .and_then(|metadata| metadata.modified())
+                    .ok();
+                let workspace = File::open(&file)
+                    .map_err(Error::from)
+                    .and_then(|source| serde_json::from_reader(source).map_err(Error::from))
+                    .map_err(|error| debug!("Skipping {}: {:#}", file.display(), error))
+                    .ok()
+                    .and_then(WorkspaceStorageEntry::into_workspace_url)?;
+                Some((modified, file, workspace))
+            })
+            .collect::<Vec<_>>();
+        if workspaces.is_empty() {
+            return None;
+        }
+        workspaces.sort_by(|(modified_a, file_a, _), (modified_b, file_b, _)| {
+            modified_b.cmp(modified_a).then(file_a.cmp(file_b))
+        });
+        let storage = Storage {
+            opened_paths_list: Some(StorageOpenedPathsList {
+                workspaces3: Some(workspaces.into_iter().map(|(_, _, url)| url).collect()),
+                entries: None,
+            }),
+        };
+        Some(Self {
+            path,
+            modified: None,
+            storage,
+        })
+    }
+
+    /// Create a new source for the `storage` read from `path`.
+    fn new(path: PathBuf, storage: Storage) -> Self {
+        let modified = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        Self {
+            path,
+            modified,
+            storage,
+        }
+    }
+
+    /// Merge the recent entries of all `sources`.
+    ///
+    /// Take entries from the most recently modified source first and drop entries
+    /// with duplicate URLs, so that the most recent data wins.
+    fn merge_recent_entries(mut sources: Vec<Self>) -> Vec<RecentEntry> {
+        sources.sort_by(|a, b| b.modified.cmp(&a.modified));
+        let mut seen = HashSet::new();
+        sources
+            .into_iter()
+            .flat_map(|source| {
+                trace!("Merging workspaces from {}", source.path.display());
+                source.storage.into_recent_entries()
+            })
+            .filter(|entry| seen.insert(entry.url.clone()))
+            .collect()
+    }
+}
+
+/// The relevant parts of VSCode's `argv.json`.
+#[derive(Debug, Deserialize)]
+struct Argv {
+    /// A permanent override for the user data directory.
+    #[serde(rename = "user-data-dir")]
+    user_data_dir: Option<PathBuf>,
+}
+
+impl Argv {
+    /// Read an `argv.json` from the given `reader`.
+    ///
+    /// VSCode writes `argv.json` with comments, so tolerate these.
+    fn read<R: Read>(mut reader: R) -> Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        serde_json::from_slice(&strip_jsonc(&data)).map_err(Into::into)
+    }
+}
+
+/// Base directories to look for configuration directories in.
+#[derive(Debug, Clone)]
+struct BaseDirs {
+    /// The home directory.
+    home_dir: PathBuf,
+    /// `$XDG_CONFIG_HOME`.
+    user_config_dir: PathBuf,
+    /// The data directory of a portable installation, from `$VSCODE_PORTABLE`.
+    portable_dir: Option<PathBuf>,
+}
+
+impl BaseDirs {
+    /// Get base directories from the environment.
+    fn from_environment() -> Self {
+        Self {
+            home_dir: glib::home_dir(),
+            user_config_dir: glib::user_config_dir(),
+            portable_dir: std::env::var_os("VSCODE_PORTABLE").map(PathBuf::from),
+        }
+    }
+
+    /// Get base directories for the app with the given `executable`.
+    ///
+    /// If `$VSCODE_PORTABLE` isn't set, use a `data` directory next to the
+    /// executable as data directory of a portable installation.
+    fn for_executable(&self, executable: &Path) -> Self {
+        let portable_dir = self
+            .portable_dir
+            .clone()
+            .or_else(|| portable_data_dir(executable));
+        Self {
+            portable_dir,
+            ..self.clone()
+        }
+    }
+}
+
+/// Find the full path of `executable` in `$PATH`, and resolve all symlinks.
+fn resolve_executable(executable: &Path) -> PathBuf {
+    glib::find_program_in_path(executable)
+        .unwrap_or_else(|| executable.to_path_buf())
+        .canonicalize()
+        .unwrap_or_else(|_| executable.to_path_buf())
+}
+
+/// Find the `data` directory of a portable installation next to `executable`.
+///
+/// Resolve symlinks first, because portable installations are often linked into `$PATH`.
+fn portable_data_dir(executable: &Path) -> Option<PathBuf> {
+    let executable = resolve_executable(executable);
+    let data_dir = executable.parent()?.join("data");
+    if data_dir.is_dir() {
+        info!(
+            "Found portable data directory {} of {}",
+            data_dir.display(),
+            executable.display()
+        );
+        Some(data_dir)
+    } else {
+        None
+    }
+}
+
+/// A kind of installation whose configuration directory a provider can use.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ConfigBase<'a> {
+    /// A native installation, with configuration in `$XDG_CONFIG_HOME`.
+    Xdg,
+    /// A Flatpak with the given app ID, with configuration in `~/.var/app/<id>/config`.
+    Flatpak(&'a str),
+    /// A snap with the given name, with configuration in `~/snap/<name>/current/.config`.
+    Snap(&'a str),
+    /// A portable installation, with configuration in `user-data` in `$VSCODE_PORTABLE`.
+    Portable,
+}
+
+impl ConfigBase<'_> {
+    /// Get the configuration directory named `dirname` for this kind of installation.
+    ///
+    /// Return `None` if there's no portable installation.
+    fn config_dir(&self, dirs: &BaseDirs, dirname: &str) -> Option<PathBuf> {
+        match self {
+            ConfigBase::Xdg => Some(dirs.user_config_dir.join(dirname)),
+            ConfigBase::Flatpak(app_id) => Some(
+                dirs.home_dir
+                    .join(".var")
+                    .join("app")
+                    .join(app_id)
+                    .join("config")
+                    .join(dirname),
+            ),
+            ConfigBase::Snap(name) => Some(
+                dirs.home_dir
+                    .join("snap")
+                    .join(name)
+                    .join("current")
+                    .join(".config")
+                    .join(dirname),
+            ),
+            ConfigBase::Portable => dirs
+                .portable_dir
+                .as_ref()
+                .map(|portable_dir| portable_dir.join("user-data")),
+        }
+    }
+}
+
+/// Watch all sources of recent workspaces in `config_dir`, and bump `changes` whenever one changes.
+///
+/// Return the monitors for all sources; they stop watching when dropped.
+/// Profiles created after this call aren't watched, but creating a profile
+/// itself counts as change.
+fn watch_workspace_sources(
+    config_dir: &Path,
+    changes: &StorageChanges,
+) -> Result<Vec<gio::FileMonitor>> {
+    WorkspaceSource::source_dirs(config_dir)
+        .into_iter()
+        .map(|(dir, prefix)| {
+            trace!("Watching {}", dir.display());
+            let monitor = gio::File::for_path(&dir)
+                .monitor_directory(gio::FileMonitorFlags::NONE, gio::NONE_CANCELLABLE)
+                .with_context(|| format!("Failed to watch {}", dir.display()))?;
+            let changes = changes.clone();
+            monitor.connect_changed(move |_, file, _, event| {
+                let relevant = event != gio::FileMonitorEvent::AttributeChanged
+                    && prefix.map_or(true, |prefix| {
+                        file.basename()
+                            .map_or(false, |name| name.to_string_lossy().starts_with(prefix))
+                    });
+                if relevant {
+                    debug!(
+                        "{:?} of {:?}, updating recent workspaces on next search",
+                        event,
+                        file.path()
+                    );
+                    changes.bump();
+                }
+            });
+            Ok(monitor)
+        })
+        .collect()
+}
+
+/// The modification time and the size of a file, to tell whether it changed.
+type FileStamp = Option<(SystemTime, u64)>;
+
 #[derive(Debug, Copy, Clone)]
 struct ConfigLocation<'a> {
-    dirname: &'a str,
+    /// Candidate names of the configuration directory, in order.
+    dirnames: &'a [&'a str],
+    /// The name of the directory in `$HOME` which contains `argv.json`.
+    argv_dirname: &'a str,
+    /// Kinds of installations to look for configuration of, in order.
+    bases: &'a [ConfigBase<'a>],
+}
+
+impl ConfigLocation<'_> {
+    /// Find the configuration directory.
+    ///
+    /// Use the user data directory from `argv.json` in the home directory if set.
+    /// Otherwise use the first configuration directory which exists, trying all
+    /// `dirnames` for every kind of installation in `bases` in turn, and fall back
+    /// to the first one if none exists.
+    fn find_config_dir(&self, dirs: &BaseDirs) -> PathBuf {
+        let argv_file = dirs.home_dir.join(self.argv_dirname).join("argv.json");
+        let argv = File::open(&argv_file)
+            .map_err(Error::from)
+            .and_then(Argv::read);
+        match argv {
+            Ok(Argv {
+                user_data_dir: Some(user_data_dir),
+            }) => {
+                info!(
+                    "Using user data directory {} from {}",
+                    user_data_dir.display(),
+                    argv_file.display()
+                );
+                dirs.home_dir.join(user_data_dir)
+            }
+            Ok(_) => self.resolve_config_dir(dirs),
+            Err(error) => {
+                if error
+                    .downcast_ref::<std::io::Error>()
+                    .map_or(false, |error| error.kind() == ErrorKind::NotFound)
+                {
+                    debug!("{} not found", argv_file.display());
+                } else {
+                    warn!("Failed to read {}: {:#}", argv_file.display(), error);
+                }
+                self.resolve_config_dir(dirs)
+            }
+        }
+    }
+
+    /// Find the first configuration directory of any kind of installation in `bases` which exists.
+    fn resolve_config_dir(&self, dirs: &BaseDirs) -> PathBuf {
+        let candidates = self
+            .bases
+            .iter()
+            .flat_map(|base| {
+                self.dirnames.iter().filter_map(move |dirname| {
+                    base.config_dir(dirs, dirname).map(|dir| (base, dir))
+                })
+            })
+            .collect::<Vec<_>>();
+        match candidates.iter().find(|(_, dir)| dir.is_dir()) {
+            Some((base, dir)) => {
+                info!(
+                    "Using configuration directory {} of {:?}",
+                    dir.display(),
+                    base
+                );
+                dir.clone()
+            }
+            None => {
+                let dir = candidates
+                    .into_iter()
+                    .next()
+                    .map(|(_, dir)| dir)
+                    .unwrap_or_else(|| dirs.user_config_dir.join(self.dirnames[0]));
+                info!(
+                    "No configuration directory of {} exists, using {}",
+                    self.dirnames.join(", "),
+                    dir.display()
+                );
+                dir
+            }
+        }
+    }
 }
 
 /// A search provider to expose from this service.
 struct ProviderDefinition<'a> {
     /// A human readable label for this provider.
     label: &'a str,
-    /// The ID (that is, the filename) of the desktop file of the corresponding app.
-    desktop_id: &'a str,
+    /// Candidate IDs (that is, filenames) of the desktop file of the corresponding app.
+    ///
+    /// Distributions name the desktop file of the same app differently; the provider
+    /// uses the first desktop file which exists.
+    desktop_ids: &'a [&'a str],
+    /// Names of the executable of the corresponding app.
+    ///
+    /// If none of `desktop_ids` exists, the provider uses the first desktop file
+    /// whose `Exec` runs one of these executables, e.g. `code-url-handler.desktop`.
+    executables: &'a [&'a str],
     /// The relative object path to expose this provider at.
     relative_obj_path: &'a str,
     /// The location of the configuration for this app.
@@ -126,123 +1057,390 @@ struct ProviderDefinition<'a> {
 }
 
 impl ProviderDefinition<'_> {
-    /// Gets the full object path for this provider.
-    fn objpath(&self) -> String {
-        format!("/de/swsnr/searchprovider/vscode/{}", self.relative_obj_path)
+    /// Find the app of this provider.
+    ///
+    /// Return the first of `desktop_ids` which exists, or else the first desktop
+    /// file of no other provider which runs one of `executables`, along with its
+    /// desktop ID.
+    fn find_app(&self) -> Option<(String, gio::DesktopAppInfo)> {
+        self.desktop_ids
+            .iter()
+            .find_map(|id| gio::DesktopAppInfo::new(id).map(|app| (id.to_string(), app)))
+            .or_else(|| {
+                if self.executables.is_empty() {
+                    return None;
+                }
+                gio::AppInfo::all()
+                    .into_iter()
+                    .filter_map(|app| app.downcast::<gio::DesktopAppInfo>().ok())
+                    // Don't take the app of another provider
+                    .filter(|app| {
+                        app.id().map_or(false, |id| {
+                            !PROVIDERS
+                                .iter()
+                                .any(|p| p.desktop_ids.contains(&id.as_str()))
+                        })
+                    })
+                    .find(|app| {
+                        app.executable()
+                            .file_name()
+                            .and_then(|name| name.to_str())
+                            .map_or(false, |name| self.executables.contains(&name))
+                    })
+                    .and_then(|app| {
+                        let desktop_id = app.id()?.to_string();
+                        info!(
+                            "Using {} for provider {}, by its executable",
+                            desktop_id, self.label
+                        );
+                        Some((desktop_id, app))
+                    })
+            })
+    }
+
+    /// The key of this provider in the configuration file.
+    ///
+    /// This is the first desktop ID without the `.desktop` extension.
+    fn key(&self) -> &str {
+        self.desktop_ids[0].trim_end_matches(".desktop")
+    }
+
+    /// The environment variable which overrides the configuration directory of this provider.
+    fn config_dir_variable(&self) -> String {
+        let name = self
+            .key()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        format!("GNOME_SEARCH_PROVIDER_{}_CONFIG_DIR", name)
+    }
+
+    /// Find the configuration directory for this provider.
+    ///
+    /// Use the directory from the environment variable of this provider if set, or
+    /// from the provider section of `config`, relative to the home directory.
+    /// Otherwise find the configuration directory of the app at its default location.
+    fn find_config_dir(&self, config: &Config, dirs: &BaseDirs) -> PathBuf {
+        let variable = self.config_dir_variable();
+        let from_env = std::env::var_os(&variable)
+            .filter(|value| !value.is_empty())
+            .map(|value| (variable.as_str(), PathBuf::from(value)));
+        let from_config = config
+            .provider(self.key())
+            .and_then(|provider| provider.config_dir.clone())
+            .map(|dir| ("configuration file", dir));
+        match from_env.or(from_config) {
+            Some((source, dir)) => {
+                let dir = dirs.home_dir.join(dir);
+                info!(
+                    "Using configuration directory {} of {} from {}",
+                    dir.display(),
+                    self.key(),
+                    source
+                );
+                dir
+            }
+            None => self.config.find_config_dir(dirs),
+        }
     }
 }
 
-/// Known search providers.
+/// The relative object path of the combined provider for all installed apps.
 ///
-/// For each definition in this array a corresponding provider file must exist in
-/// `providers/`; the file must refer to the same `desktop_id` and the same object path.
-/// The object path must be unique for each desktop ID, to ensure that this service always
-/// launches the right application associated with the search provider.
-const PROVIDERS: &[ProviderDefinition] = &[
-    // The standard Arch Linux code package from community
-    ProviderDefinition {
-        label: "Code OSS (Arch Linux)",
-        desktop_id: "code-oss.desktop",
-        relative_obj_path: "arch/codeoss",
-        config: ConfigLocation {
-            dirname: "Code - OSS",
-        },
-    },
-    // The binary AUR package for visual studio code: https://aur.archlinux.org/packages/visual-studio-code-bin/
-    ProviderDefinition {
-        label: "Visual Studio Code (AUR package)",
-        desktop_id: "visual-studio-code.desktop",
-        relative_obj_path: "aur/visualstudiocode",
-        config: ConfigLocation { dirname: "Code" },
-    },
-    // The standard codium package on Linux from here: https://github.com/VSCodium/vscodium.
-    // Should work for most Linux distributions packaged from here.
-    ProviderDefinition {
-        label: "VSCodium",
-        desktop_id: "codium.desktop",
-        relative_obj_path: "codium",
-        config: ConfigLocation {
-            dirname: "VSCodium",
-        },
-    },
-    // The official install packages from https://code.visualstudio.com/download.
-    ProviderDefinition {
-        label: "Visual Studio Code (Official package)",
-        desktop_id: "code.desktop",
-        relative_obj_path: "official/code",
-        config: ConfigLocation { dirname: "Code" },
-    },
-];
-
-/// A recent workspace of a VSCode variant.
-#[derive(Debug, PartialEq)]
-struct RecentWorkspace {
-    /// The human readable nfame.
-    name: String,
-    /// The workspace URL.
-    url: String,
-}
-
-fn recent_item(url: String) -> Result<AppLaunchItem> {
-    if let Some(name) = url.split('/').last() {
-        let item = AppLaunchItem {
-            name: name.to_string(),
-            uri: url,
-        };
-        trace!("Found recent workspace item {:?}", item);
-        Ok(item)
-    } else {
-        Err(anyhow!("Failed to extract workspace name from URL {}", url))
+/// A corresponding provider file exists in `providers/combined/`; in contrast to
+/// the files for the individual providers it's only installed on request, because
+/// Gnome shell would otherwise show all workspaces twice.
+const COMBINED_RELATIVE_OBJ_PATH: &str = "combined";
+
+/// The label of the combined provider.
+const COMBINED_LABEL: &str = "All installed variants (combined)";
+
+// The known search providers, generated from providers.toml
+include!(concat!(env!("OUT_DIR"), "/providers.rs"));
+
+/// The relevant parts of the `product.json` of a VSCode build.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Product {
+    /// The short name, which VSCode uses as name of its configuration directory.
+    name_short: String,
+    /// The full name of the build.
+    name_long: Option<String>,
+}
+
+impl Product {
+    /// Find and read the `product.json` of the VSCode build which contains `executable`.
+    ///
+    /// Look for `resources/app/product.json`, `product.json` and
+    /// `lib/*/resources/app/product.json` in the directory of the resolved
+    /// `executable` and its two parent directories.
+    fn find_for_executable(executable: &Path) -> Option<(PathBuf, Self)> {
+        let executable = resolve_executable(executable);
+        executable
+            .ancestors()
+            .skip(1)
+            .take(3)
+            .flat_map(|dir| {
+                // Nix and some distributions install VSCode to lib/<name> and
+                // only link or wrap the executable in bin/
+                let mut installations = std::fs::read_dir(dir.join("lib"))
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path().join("resources/app/product.json"))
+                    .collect::<Vec<_>>();
+                installations.sort();
+                [
+                    dir.join("resources/app/product.json"),
+                    dir.join("product.json"),
+                ]
+                .into_iter()
+                .chain(installations)
+            })
+            .find(|path| path.is_file())
+            .and_then(|path| {
+                let product = File::open(&path)
+                    .map_err(Error::from)
+                    .and_then(|source| serde_json::from_reader(source).map_err(Error::from))
+                    .map_err(|error| debug!("Skipping {}: {:#}", path.display(), error))
+                    .ok()?;
+                Some((path, product))
+            })
     }
 }
 
-#[derive(Debug)]
-struct VscodeWorkspacesSource {
-    app_id: AppId,
-    /// The configuration directory.
+/// Find all directories in `config_root` which look like the configuration directory of VSCode.
+///
+/// These directories contain either `storage.json` or a global state database.
+fn find_storage_dirs(config_root: &Path) -> Vec<PathBuf> {
+    let mut dirs = match std::fs::read_dir(config_root) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|dir| {
+                dir.join("storage.json").is_file()
+                    || dir.join("User/globalStorage/state.vscdb").is_file()
+            })
+            .collect::<Vec<_>>(),
+        Err(error) => {
+            debug!("Failed to read {}: {}", config_root.display(), error);
+            Vec::new()
+        }
+    };
+    dirs.sort();
+    dirs
+}
+
+/// Add the first existing directory of `dirnames` in all extra configuration `roots` to `config_dir`.
+///
+/// Skip roots without any of `dirnames`.
+fn with_extra_config_dirs(
     config_dir: PathBuf,
+    dirnames: &[&str],
+    roots: &[PathBuf],
+) -> Vec<PathBuf> {
+    let mut dirs = vec![config_dir];
+    for root in roots {
+        match dirnames
+            .iter()
+            .map(|dirname| root.join(dirname))
+            .find(|dir| dir.is_dir())
+        {
+            Some(dir) if !dirs.contains(&dir) => {
+                info!("Using extra configuration directory {}", dir.display());
+                dirs.push(dir);
+            }
+            _ => debug!("Skipping extra configuration root {}", root.display()),
+        }
+    }
+    dirs
 }
 
-#[async_trait]
-impl AsyncItemsSource<AppLaunchItem> for VscodeWorkspacesSource {
-    type Err = Error;
-
-    #[instrument()]
-    async fn find_recent_items(&self) -> Result<IdMap<AppLaunchItem>, Self::Err> {
-        info!("Finding recent workspaces for {}", self.app_id);
-        let span = Span::current();
-        // Move to the main thread and then asynchronously read recent items through Gio,
-        // and get them sent back to us via a oneshot channel.  We can't run the future
-        // right away, because Gio futures aren't Send.
-        let (send, recv) = futures_channel::oneshot::channel();
-        let dir = self.config_dir.clone();
-        glib::MainContext::default().invoke(move || {
-            glib::MainContext::default().spawn_local(
-                async move { send.send(Storage::from_dir(dir).await).unwrap() }.instrument(span),
+/// An installed app which we don't know of but which looks like a build of VSCode.
+struct DetectedApp {
+    /// A human readable label for the app.
+    label: String,
+    /// The app.
+    app: gio::DesktopAppInfo,
+    /// The configuration directory of the app.
+    config_dir: PathBuf,
+    /// The name of the configuration directory.
+    dirname: String,
+}
+
+/// Normalize the name of an app or a configuration directory for comparison.
+///
+/// Keep only ASCII letters and digits, in lowercase, so that e.g. `code-oss`
+/// matches `Code - OSS`.
+fn normalized_app_name(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Find the directory among `candidates` whose name matches one of `names`.
+///
+/// Return the index of the first matching directory, trying `names` in order.
+fn match_config_dir(names: &[String], candidates: &[PathBuf]) -> Option<usize> {
+    names
+        .iter()
+        .map(|name| normalized_app_name(name))
+        .filter(|name| !name.is_empty())
+        .find_map(|name| {
+            candidates.iter().position(|dir| {
+                dir.file_name()
+                    .and_then(|dirname| dirname.to_str())
+                    .map_or(false, |dirname| normalized_app_name(dirname) == name)
+            })
+        })
+}
+
+/// Names in the desktop entry of `app` which may refer to its configuration directory.
+///
+/// VSCode sets the window class to the name of its configuration directory, so
+/// try `StartupWMClass` first, and then the name of the executable in `Exec`.
+fn desktop_entry_names(app: &gio::DesktopAppInfo) -> Vec<String> {
+    app.startup_wm_class()
+        .map(|class| class.to_string())
+        .into_iter()
+        .chain(
+            app.executable()
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(ToOwned::to_owned),
+        )
+        .collect()
+}
+
+/// Detect unknown builds of VSCode in the configuration directory of `dirs`.
+///
+/// Find all directories in `$XDG_CONFIG_HOME` with VSCode storage, except for
+/// the `known_config_dirs` of known apps, and match them to installed apps whose
+/// `product.json` refers to the same configuration directory.  For apps without
+/// a matching `product.json` fall back to the `StartupWMClass` and `Exec` of
+/// their desktop entry.
+fn detect_apps(dirs: &BaseDirs, known_config_dirs: &[PathBuf]) -> Vec<DetectedApp> {
+    let mut candidates = find_storage_dirs(&dirs.user_config_dir)
+        .into_iter()
+        .filter(|dir| !known_config_dirs.contains(dir))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return Vec::new();
+    }
+    let mut detected = Vec::new();
+    for app in gio::AppInfo::all() {
+        let app = match app.downcast::<gio::DesktopAppInfo>() {
+            Ok(app) => app,
+            Err(_) => continue,
+        };
+        let desktop_id = match app.id() {
+            Some(id) => id,
+            None => continue,
+        };
+        if PROVIDERS
+            .iter()
+            .any(|p| p.desktop_ids.contains(&desktop_id.as_str()))
+        {
+            continue;
+        }
+        let found = Product::find_for_executable(&app.executable())
+            .and_then(|(path, product)| {
+                let config_dir = dirs.user_config_dir.join(&product.name_short);
+                let index = candidates.iter().position(|dir| dir == &config_dir)?;
+                let label = product
+                    .name_long
+                    .unwrap_or_else(|| product.name_short.clone());
+                Some((index, label, product.name_short, path.display().to_string()))
+            })
+            .or_else(|| {
+                let index = match_config_dir(&desktop_entry_names(&app), &candidates)?;
+                let dirname = candidates[index].file_name()?.to_str()?.to_string();
+                Some((
+                    index,
+                    app.name().to_string(),
+                    dirname,
+                    "desktop entry".to_string(),
+                ))
+            });
+        if let Some((index, label, dirname, source)) = found {
+            let config_dir = candidates.remove(index);
+            info!(
+                "Detected app {} with configuration directory {} from {}",
+                desktop_id,
+                config_dir.display(),
+                source
             );
-        });
+            detected.push(DetectedApp {
+                label,
+                app,
+                config_dir,
+                dirname,
+            });
+        }
+    }
+    detected
+}
 
-        let urls = recv.await.unwrap()?.into_workspace_urls();
-        let mut items = IndexMap::new();
-        for url in urls {
-            trace!("Discovered workspace url {}", url);
-            let id = format!("vscode-search-provider-{}-{}", self.app_id, &url);
-            match recent_item(url) {
-                Ok(item) => {
-                    items.insert(id, item);
-                }
-                Err(err) => {
-                    warn!("Skipping workspace: {}", err)
-                }
-            }
+/// The command to run `app` with additional arguments.
+///
+/// The executable of a Flatpak app is `flatpak` itself, so run the app through
+/// `flatpak run` instead.  Snap apps often run through `env`; run their command
+/// in `/snap/bin` instead.
+fn launch_command(app: &gio::DesktopAppInfo) -> Vec<OsString> {
+    if let Some(id) = app.string("X-Flatpak") {
+        vec!["flatpak".into(), "run".into(), id.as_str().into()]
+    } else if let Some(name) = app.string("X-SnapInstanceName") {
+        vec![Path::new("/snap/bin").join(name.as_str()).into()]
+    } else {
+        vec![app.executable().into()]
+    }
+}
+
+/// The relative object path for a detected app with the given `desktop_id`.
+///
+/// Gnome shell doesn't know of search providers for detected apps, so this path
+/// only identifies the workspaces of the app in the combined provider.
+fn detected_relative_obj_path(desktop_id: &str) -> String {
+    let name = desktop_id
+        .trim_end_matches(".desktop")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    format!("detected/{}", name)
+}
+
+/// Where to serve search providers on the bus.
+///
+/// Overriding the defaults allows to run a development build next to the installed service.
+#[derive(Debug, Clone)]
+struct BusLocation {
+    /// The name to request on the bus.
+    busname: String,
+    /// The prefix for object paths of all search providers.
+    objpath_prefix: String,
+}
+
+impl Default for BusLocation {
+    fn default() -> Self {
+        Self {
+            busname: BUSNAME.to_string(),
+            objpath_prefix: OBJPATH_PREFIX.to_string(),
         }
-        info!("Found {} workspace(s) for {}", items.len(), self.app_id);
-        Ok(items)
     }
 }
 
-/// The name to request on the bus.
-const BUSNAME: &str = "de.swsnr.searchprovider.VSCode";
+impl BusLocation {
+    /// Get the full object path for the given `relative_obj_path`.
+    fn objpath(&self, relative_obj_path: &str) -> String {
+        format!("{}/{}", self.objpath_prefix, relative_obj_path)
+    }
+}
 
 async fn tick(connection: zbus::Connection) {
     loop {
@@ -250,46 +1448,320 @@ async fn tick(connection: zbus::Connection) {
     }
 }
 
+/// How to find installed apps and create search providers for them.
+struct ProviderSetup {
+    location: BusLocation,
+    config: Config,
+    settings: ProviderSettings,
+    launcher: AppLaunchClient,
+    activity: ActivityTracker,
+    frecency: Frecency,
+}
+
+impl ProviderSetup {
+    /// Find all installed apps, and watch the sources of their recent workspaces.
+    ///
+    /// Return known and custom apps along with their object paths, detected apps,
+    /// and the monitors for all apps.
+    fn find_apps(
+        &self,
+    ) -> (
+        Vec<(String, ProviderApp)>,
+        Vec<ProviderApp>,
+        Vec<gio::FileMonitor>,
+    ) {
+        let location = &self.location;
+        let config = &self.config;
+        let base_dirs = BaseDirs::from_environment();
+        // List all apps first; gio only tells about changes to installed apps after
+        // listing them
+        let _ = gio::AppInfo::all();
+        let mut monitors = Vec::new();
+        let extra_roots = config
+            .storage
+            .extra_config_roots
+            .iter()
+            .map(|root| base_dirs.home_dir.join(root))
+            .collect::<Vec<_>>();
+        let mut provider_app = |label: &str,
+                                desktop_id: &str,
+                                objpath: String,
+                                app: &gio::DesktopAppInfo,
+                                config_dirs: Vec<PathBuf>| {
+            let changes = StorageChanges::default();
+            let app_monitors = config_dirs
+                .iter()
+                .map(|dir| watch_workspace_sources(dir, &changes))
+                .collect::<Result<Vec<_>>>();
+            let changes = match app_monitors {
+                Ok(app_monitors) => {
+                    monitors.extend(app_monitors.into_iter().flatten());
+                    Some(changes)
+                }
+                Err(error) => {
+                    warn!(
+                        "Reading recent workspaces of {} on every search: {:#}",
+                        desktop_id, error
+                    );
+                    None
+                }
+            };
+            ProviderApp {
+                label: label.to_string(),
+                desktop_id: desktop_id.to_string(),
+                objpath,
+                icon: app
+                    .icon()
+                    .and_then(|icon| IconExt::to_string(&icon))
+                    .map(Into::into),
+                config_dirs,
+                command: launch_command(app),
+                server_url: None,
+                changes,
+            }
+        };
+        info!("Looking for installed apps");
+        let mut apps = PROVIDERS
+            .iter()
+            .filter_map(|provider| {
+                if !config.is_enabled(provider.key()) {
+                    info!("Skipping disabled provider {}", provider.key());
+                    return None;
+                }
+                let (desktop_id, app) = provider.find_app()?;
+                info!("Found app {}", desktop_id);
+                let objpath = location.objpath(provider.relative_obj_path);
+                let config_dir =
+                    provider.find_config_dir(config, &base_dirs.for_executable(&app.executable()));
+                Some((
+                    objpath.clone(),
+                    provider_app(
+                        provider.label,
+                        &desktop_id,
+                        objpath,
+                        &app,
+                        with_extra_config_dirs(config_dir, provider.config.dirnames, &extra_roots),
+                    ),
+                ))
+            })
+            .collect::<Vec<_>>();
+        for custom in &config.custom_providers {
+            match gio::DesktopAppInfo::new(&custom.desktop_id) {
+                Some(app) => {
+                    info!(
+                        "Found app {} of custom provider {}",
+                        custom.desktop_id, custom.label
+                    );
+                    let objpath = location.objpath(&custom.object_path);
+                    let config_dir = base_dirs.home_dir.join(&custom.config_dir);
+                    let custom_app = ProviderApp {
+                        server_url: custom.server_url.clone(),
+                        ..provider_app(
+                            &custom.label,
+                            &custom.desktop_id,
+                            objpath.clone(),
+                            &app,
+                            vec![config_dir],
+                        )
+                    };
+                    apps.push((objpath, custom_app));
+                }
+                None => warn!(
+                    "App {} of custom provider {} not found",
+                    custom.desktop_id, custom.label
+                ),
+            }
+        }
+        info!("Looking for unknown builds of VSCode");
+        let known_config_dirs = apps
+            .iter()
+            .flat_map(|(_, app)| app.config_dirs.iter().cloned())
+            .collect::<Vec<_>>();
+        let detected_apps = detect_apps(&base_dirs, &known_config_dirs)
+            .into_iter()
+            .filter_map(|detected| {
+                let desktop_id = detected.app.id()?;
+                let objpath = location.objpath(&detected_relative_obj_path(&desktop_id));
+                Some(provider_app(
+                    &detected.label,
+                    &desktop_id,
+                    objpath,
+                    &detected.app,
+                    with_extra_config_dirs(
+                        detected.config_dir,
+                        &[detected.dirname.as_str()],
+                        &extra_roots,
+                    ),
+                ))
+            })
+            .collect::<Vec<_>>();
+        (apps, detected_apps, monitors)
+    }
+
+    /// Create search providers for all installed apps.
+    ///
+    /// Return the object path of every provider along with the provider, and the
+    /// monitors for all apps.
+    fn providers(&self) -> (Vec<(String, VscodeSearchProvider)>, Vec<gio::FileMonitor>) {
+        let (apps, detected_apps, monitors) = self.find_apps();
+        // Create a provider for every known app, and a combined provider for all apps,
+        // including detected ones
+        let mut providers = apps
+            .iter()
+            .map(|(path, app)| {
+                (
+                    path.clone(),
+                    VscodeSearchProvider::new(
+                        vec![app.clone()],
+                        self.launcher.clone(),
+                        self.activity.clone(),
+                        self.frecency.clone(),
+                        self.settings.clone(),
+                    ),
+                )
+            })
+            .collect::<Vec<_>>();
+        if !apps.is_empty() || !detected_apps.is_empty() {
+            providers.push((
+                self.location.objpath(COMBINED_RELATIVE_OBJ_PATH),
+                VscodeSearchProvider::new(
+                    apps.into_iter()
+                        .map(|(_, app)| app)
+                        .chain(detected_apps)
+                        .collect(),
+                    self.launcher.clone(),
+                    self.activity.clone(),
+                    self.frecency.clone(),
+                    self.settings.clone(),
+                ),
+            ));
+        }
+        (providers, monitors)
+    }
+}
+
+/// The object path of every provider in `providers`, with the desktop IDs of its apps.
+fn provider_apps(providers: &[(String, VscodeSearchProvider)]) -> Vec<(String, Vec<String>)> {
+    providers
+        .iter()
+        .map(|(path, provider)| {
+            (
+                path.clone(),
+                provider
+                    .apps()
+                    .iter()
+                    .map(|app| app.desktop_id.clone())
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+/// How long to wait before updating search providers after installed apps changed.
+///
+/// Installing or removing a package often changes several desktop files in a row.
+const APP_CHANGES_DELAY: Duration = Duration::from_secs(1);
+
+/// The search providers registered on the bus.
+struct ProviderRegistry {
+    setup: ProviderSetup,
+    connection: zbus::Connection,
+    /// The object path of every registered provider, with the desktop IDs of its apps.
+    registered: RefCell<Vec<(String, Vec<String>)>>,
+    /// Monitors for the sources of recent workspaces of all apps.
+    monitors: RefCell<Vec<gio::FileMonitor>>,
+    /// Whether an update of the registered providers is pending.
+    update_pending: Cell<bool>,
+}
+
+impl ProviderRegistry {
+    /// Register search providers for the currently installed apps.
+    ///
+    /// Keep registered providers and their cached workspaces if the installed
+    /// apps didn't change.
+    async fn update(&self) -> Result<()> {
+        let (providers, monitors) = self.setup.providers();
+        if provider_apps(&providers) == *self.registered.borrow() {
+            debug!("Installed apps unchanged, keeping search providers");
+            return Ok(());
+        }
+        let object_server = self.connection.object_server();
+        for (path, _) in self.registered.replace(Vec::new()) {
+            debug!("Removing search provider at {}", path);
+            object_server
+                .remove::<VscodeSearchProvider, _>(path.as_str())
+                .await?;
+        }
+        info!("Registering {} search provider(s)", providers.len());
+        for entry in provider_apps(&providers).into_iter().zip(providers) {
+            let ((path, desktop_ids), (_, provider)) = entry;
+            debug!(
+                "Registering search provider for apps {:?} at {}",
+                desktop_ids, path
+            );
+            object_server.at(path.as_str(), provider).await?;
+            self.registered.borrow_mut().push((path, desktop_ids));
+        }
+        self.monitors.replace(monitors);
+        Ok(())
+    }
+
+    /// Update registered search providers after a short delay.
+    ///
+    /// Do nothing if an update is already pending.
+    fn schedule_update(self: &Rc<Self>) {
+        if !self.update_pending.replace(true) {
+            let registry = self.clone();
+            glib::MainContext::ref_thread_default().spawn_local(async move {
+                glib::timeout_future(APP_CHANGES_DELAY).await;
+                registry.update_pending.set(false);
+                if let Err(error) = registry.update().await {
+                    error!("Failed to update search providers: {:#}", error);
+                }
+            });
+        }
+    }
+}
+
 struct Service {
     app_launch_service: AppLaunchService,
     connection: zbus::Connection,
+    activity: ActivityTracker,
+    /// Monitors installed apps to update search providers.
+    app_monitor: gio::AppInfoMonitor,
 }
 
 /// Starts the DBUS service loop.
 ///
 /// Connect to the ession bus and register DBus objects for every provider
-/// whose underlying VSCode variant is installed.
+/// whose underlying VSCode variant is installed, and a combined provider for
+/// all installed variants, at the given `location`, with the given provider `settings`.
+/// Update the registered providers whenever apps are installed or removed.
 ///
 /// Then register the connection on the Glib main loop and handle incoming messages.
-async fn start_dbus_service(log_control: LogControl) -> Result<Service> {
+async fn start_dbus_service(
+    log_control: LogControl,
+    location: &BusLocation,
+    config: Config,
+    settings: ProviderSettings,
+) -> Result<Service> {
     let app_launch_service = AppLaunchService::new();
-    // Create providers for all apps we find
-    let user_config_dir = glib::user_config_dir();
-    info!("Looking for installed apps");
-    let providers = PROVIDERS
-        .iter()
-        .filter_map(|provider| {
-            gio::DesktopAppInfo::new(provider.desktop_id).map(|app| {
-                info!("Found app {}", provider.desktop_id);
-                (
-                    provider.objpath(),
-                    AppItemSearchProvider::new(
-                        app.into(),
-                        VscodeWorkspacesSource {
-                            app_id: provider.desktop_id.into(),
-                            config_dir: user_config_dir.join(provider.config.dirname),
-                        },
-                        app_launch_service.client(),
-                    ),
-                )
-            })
-        })
-        .collect::<Vec<_>>();
+    let activity = ActivityTracker::new();
+    let setup = ProviderSetup {
+        location: location.clone(),
+        config,
+        settings,
+        launcher: app_launch_service.client(),
+        activity: activity.clone(),
+        frecency: Frecency::load(Frecency::default_path()),
+    };
+    let (providers, monitors) = setup.providers();
+    let registered = provider_apps(&providers);
 
     info!(
         "Registering {} search provider(s) on {}",
         providers.len(),
-        BUSNAME
+        location.busname
     );
     let connection = providers
         .into_iter()
@@ -297,15 +1769,19 @@ async fn start_dbus_service(log_control: LogControl) -> Result<Service> {
             zbus::ConnectionBuilder::session()?,
             |b, (path, provider)| {
                 debug!(
-                    "Registering search provider for app {} at {}",
-                    provider.app().id(),
+                    "Registering search provider for apps {:?} at {}",
+                    provider
+                        .apps()
+                        .iter()
+                        .map(|app| app.desktop_id.as_str())
+                        .collect::<Vec<_>>(),
                     path
                 );
                 b.serve_at(path, provider)
             },
         )?
         .serve_at("/org/freedesktop/LogControl1", log_control)?
-        .name(BUSNAME)?
+        .name(location.busname.as_str())?
         // We disable the internal executor because we'd like to run the connection
         // exclusively on the glib mainloop, and thus tick it manually (see below).
         .internal_executor(false)
@@ -316,13 +1792,108 @@ async fn start_dbus_service(log_control: LogControl) -> Result<Service> {
     // Manually tick the connection on the glib mainloop to make all code in zbus run on the mainloop.
     glib::MainContext::ref_thread_default().spawn(tick(connection.clone()));
 
-    info!("Acquired name {}, serving search providers", BUSNAME);
+    info!(
+        "Acquired name {}, serving search providers",
+        location.busname
+    );
+    let registry = Rc::new(ProviderRegistry {
+        setup,
+        connection: connection.clone(),
+        registered: RefCell::new(registered),
+        monitors: RefCell::new(monitors),
+        update_pending: Cell::new(false),
+    });
+    let app_monitor = gio::AppInfoMonitor::get();
+    app_monitor.connect_changed(move |_| {
+        info!("Installed apps changed, updating search providers");
+        registry.schedule_update();
+    });
     Ok(Service {
         app_launch_service,
         connection,
+        activity,
+        app_monitor,
     })
 }
 
+/// Find all objects with the search provider interface in the introspection `xml` of `root`.
+///
+/// Walk the nested nodes of `xml`, because the object server introspects all children of
+/// an object, and return the paths of all nodes with the search provider interface.
+fn search_provider_paths(root: &str, xml: &str) -> Vec<String> {
+    let mut path = vec![root.to_string()];
+    let mut paths = Vec::new();
+    for line in xml.lines().map(str::trim) {
+        if let Some(name) = line
+            .strip_prefix("<node name=\"")
+            .and_then(|rest| rest.split('"').next())
+        {
+            if !line.ends_with("/>") {
+                let child = format!("{}/{}", path.last().map_or("", String::as_str), name);
+                path.push(child);
+            }
+        } else if line == "</node>" {
+            path.pop();
+        } else if line.starts_with("<interface name=\"org.gnome.Shell.SearchProvider2\"") {
+            paths.extend(path.last().cloned());
+        }
+    }
+    paths
+}
+
+/// Get the object paths and labels of all search providers a running service at `location` exposes.
+///
+/// Introspect all objects below the object path prefix on the bus, and return those which
+/// implement the search provider interface, with the label of built-in providers.
+fn list_registered_objects(location: &BusLocation) -> Result<Vec<(String, Option<&'static str>)>> {
+    let connection = zbus::blocking::Connection::session()
+        .with_context(|| "Failed to connect to session bus")?;
+    let root = location.objpath_prefix.as_str();
+    let xml = match zbus::blocking::fdo::IntrospectableProxy::builder(&connection)
+        .destination(location.busname.as_str())?
+        .path(root)?
+        .build()?
+        .introspect()
+    {
+        // The service has no objects below the prefix, i.e. no providers at all
+        Err(zbus::fdo::Error::UnknownObject(_)) => return Ok(Vec::new()),
+        result => result
+            .with_context(|| format!("Failed to introspect {} on {}", root, location.busname))?,
+    };
+    let labels = PROVIDERS
+        .iter()
+        .map(|provider| (location.objpath(provider.relative_obj_path), provider.label))
+        .chain(std::iter::once((
+            location.objpath(COMBINED_RELATIVE_OBJ_PATH),
+            COMBINED_LABEL,
+        )))
+        .collect::<HashMap<_, _>>();
+    Ok(search_provider_paths(root, &xml)
+        .into_iter()
+        .map(|path| {
+            let label = labels.get(&path).copied();
+            (path, label)
+        })
+        .collect())
+}
+
+/// Read recent workspaces directly from the storage file at `path` and print them.
+fn dump_storage_file(path: &Path) -> Result<()> {
+    let storage = Storage::from_file(path)
+        .with_context(|| format!("Failed to read storage from {}", path.display()))?;
+    let mut workspaces = IndexMap::new();
+    for url in storage.into_workspace_urls() {
+        add_workspace(&mut workspaces, "", "storage-file", url, None);
+    }
+    for workspace in workspaces.values() {
+        println!(
+            "{}\t{}\t{}",
+            workspace.name, workspace.description, workspace.url
+        );
+    }
+    Ok(())
+}
+
 fn app() -> clap::App<'static> {
     use clap::*;
     app_from_crate!()
@@ -338,21 +1909,132 @@ Set $RUST_LOG to control the log level",
                 .long("--providers")
                 .help("List all providers"),
         )
+        .arg(
+            Arg::new("list_objects")
+                .long("--list-objects")
+                .help("List object paths of all providers a running service exposes"),
+        )
+        .arg(
+            Arg::new("storage_file")
+                .long("--storage-file")
+                .takes_value(true)
+                .allow_invalid_utf8(true)
+                .value_name("PATH")
+                .help("Print recent workspaces from the storage.json or state.vscdb at PATH"),
+        )
         .arg(
             Arg::new("journal_log")
                 .long("--journal-log")
                 .help("Directly log to the systemd journal instead of stdout"),
         )
+        .arg(
+            Arg::new("bus_name")
+                .long("--bus-name")
+                .env("GNOME_SEARCH_PROVIDERS_VSCODE_BUS_NAME")
+                .takes_value(true)
+                .value_name("NAME")
+                .default_value(BUSNAME)
+                .help("The name to request on the bus"),
+        )
+        .arg(
+            Arg::new("object_path_prefix")
+                .long("--object-path-prefix")
+                .env("GNOME_SEARCH_PROVIDERS_VSCODE_OBJECT_PATH_PREFIX")
+                .takes_value(true)
+                .value_name("PATH")
+                .default_value(OBJPATH_PREFIX)
+                .validator(|s| {
+                    if s.starts_with('/') && !s.ends_with('/') {
+                        Ok(())
+                    } else {
+                        Err("must start and must not end with /")
+                    }
+                })
+                .help("The prefix for object paths of all providers"),
+        )
+        .arg(
+            Arg::new("config")
+                .long("--config")
+                .takes_value(true)
+                .allow_invalid_utf8(true)
+                .value_name("FILE")
+                .help("Read configuration from FILE")
+                .long_help(
+                    "Read configuration from FILE instead of \
+$XDG_CONFIG_HOME/gnome-search-providers-vscode/config.toml",
+                ),
+        )
+        .arg(
+            Arg::new("extra_config_root")
+                .long("--extra-config-root")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .allow_invalid_utf8(true)
+                .value_name("DIR")
+                .help("Also look for configuration directories of apps in DIR")
+                .long_help(
+                    "Also look for configuration directories of apps in DIR, \
+in addition to extra-config-roots from the configuration file.  Relative paths \
+are relative to $HOME.",
+                ),
+        )
+        .arg(
+            Arg::new("placeholder_when_empty")
+                .long("--placeholder-when-empty")
+                .help("Show a placeholder result if there are no recent workspaces"),
+        )
+        .arg(
+            Arg::new("include_recent_files")
+                .long("--include-recent-files")
+                .help("Also show recently opened files"),
+        )
+        .arg(
+            Arg::new("hide_missing_workspaces")
+                .long("--hide-missing-workspaces")
+                .help("Hide local workspaces which no longer exist"),
+        )
+        .arg(
+            Arg::new("idle_timeout")
+                .long("--idle-timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .validator(|s| s.parse::<u64>())
+                .help("Release the bus name and quit after SECONDS without searches"),
+        )
 }
 
 fn main() {
     let matches = app().get_matches();
+    let location = BusLocation {
+        busname: matches.value_of("bus_name").unwrap().to_string(),
+        objpath_prefix: matches.value_of("object_path_prefix").unwrap().to_string(),
+    };
     if matches.is_present("providers") {
         let mut labels: Vec<&'static str> = PROVIDERS.iter().map(|p| p.label).collect();
         labels.sort_unstable();
         for label in labels {
             println!("{}", label)
         }
+    } else if let Some(path) = matches.value_of_os("storage_file") {
+        if let Err(error) = dump_storage_file(Path::new(path)) {
+            eprintln!("{:#}", error);
+            std::process::exit(1);
+        }
+    } else if matches.is_present("list_objects") {
+        match list_registered_objects(&location) {
+            Ok(objects) => {
+                for (path, label) in objects {
+                    match label {
+                        Some(label) => println!("{}\t{}", path, label),
+                        None => println!("{}", path),
+                    }
+                }
+            }
+            Err(error) => {
+                eprintln!("Failed to list objects: {:#}", error);
+                std::process::exit(1);
+            }
+        }
     } else {
         let log_control = setup_logging_for_service();
 
@@ -366,8 +2048,97 @@ fn main() {
         let context = glib::MainContext::default();
         context.push_thread_default();
 
-        match context.block_on(start_dbus_service(log_control)) {
+        let idle_timeout = if matches.is_present("idle_timeout") {
+            Some(Duration::from_secs(
+                matches.value_of_t_or_exit("idle_timeout"),
+            ))
+        } else {
+            None
+        };
+
+        let config_file = matches
+            .value_of_os("config")
+            .map_or_else(Config::default_path, PathBuf::from);
+        let validate = |mut config: Config| -> Result<_> {
+            let keys = PROVIDERS.iter().map(|p| p.key()).collect::<Vec<_>>();
+            let mut reserved = PROVIDERS
+                .iter()
+                .map(|p| p.relative_obj_path)
+                .collect::<Vec<_>>();
+            reserved.push(COMBINED_RELATIVE_OBJ_PATH);
+            let (exclude, description) = config
+                .validate_providers(&keys)
+                .and_then(|_| config.validate_custom_providers(&reserved))
+                .and_then(|_| config.filter.exclude_set(&glib::home_dir()))
+                .and_then(|exclude| {
+                    let description = config.results.description_template()?;
+                    Ok((exclude, description))
+                })
+                .with_context(|| format!("Invalid configuration in {}", config_file.display()))?;
+            // Providers of packagers come before custom providers of the user, but
+            // custom providers of the user take precedence over their object paths
+            reserved.extend(
+                config
+                    .custom_providers
+                    .iter()
+                    .map(|provider| provider.object_path.as_str()),
+            );
+            let mut providers =
+                ProviderDropIn::load_all(&ProviderDropIn::default_dirs(), &reserved);
+            providers.append(&mut config.custom_providers);
+            config.custom_providers = providers;
+            Ok((config, exclude, description))
+        };
+        // Don't let a typo in the configuration take away search results altogether
+        let (mut config, exclude, description) = Config::load(&config_file)
+            .and_then(validate)
+            .unwrap_or_else(|error| {
+                error!(
+                    "Failed to load configuration: {:#}; using default configuration",
+                    error
+                );
+                validate(Config::default()).expect("Default configuration must be valid")
+            });
+
+        if let Some(roots) = matches.values_of_os("extra_config_root") {
+            config
+                .storage
+                .extra_config_roots
+                .extend(roots.map(PathBuf::from));
+        }
+
+        // Flags on the command line enable settings regardless of the configuration
+        let settings = ProviderSettings {
+            placeholder_when_empty: config.results.placeholder_when_empty
+                || matches.is_present("placeholder_when_empty"),
+            include_recent_files: config.results.include_recent_files
+                || matches.is_present("include_recent_files"),
+            hide_missing_workspaces: config.filter.hide_missing_workspaces
+                || matches.is_present("hide_missing_workspaces"),
+            match_project_names: config.results.match_project_names,
+            exclude,
+            pinned: config.results.pinned_urls(&glib::home_dir()),
+            description,
+            max_storage_size: config.storage.max_size(),
+            max_results: config.results.max_results(),
+            min_score: config.results.min_score,
+        };
+
+        match context.block_on(start_dbus_service(log_control, &location, config, settings)) {
             Ok(service) => {
+                // Keep watching installed apps while the main loop runs
+                let _app_monitor = service.app_monitor;
+                let main_loop = create_main_loop(&context);
+                if let Some(timeout) = idle_timeout {
+                    info!("Quitting after {}s without searches", timeout.as_secs());
+                    context.spawn_local(quit_when_idle(
+                        service.connection.clone(),
+                        location.busname,
+                        service.activity,
+                        timeout,
+                        main_loop.clone(),
+                    ));
+                }
                 let _ = service.app_launch_service.start(
                     &context,
                     service.connection,
@@ -377,7 +2148,7 @@ fn main() {
                         documentation: vec![env!("CARGO_PKG_HOMEPAGE").to_string()],
                     },
                 );
-                create_main_loop(&context).run();
+                main_loop.run();
             }
             Err(error) => {
                 error!("Failed to start DBus server: {:#}", error);
@@ -385,16 +2156,72 @@ fn main() {
             }
         }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::app;
-    use crate::Storage;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::app;
+    use crate::{
+        detected_relative_obj_path, find_storage_dirs, gio, glib, is_permission_denied,
+        is_truncated, match_config_dir, search_provider_paths, storage_read_error, strip_jsonc,
+        with_extra_config_dirs, Argv, BaseDirs, BusLocation, Config, ConfigBase, ConfigLocation,
+        Product, ProviderDefinition, RecentEntry, RecentUrl, Storage, WorkspaceSource,
+    };
+    use std::io::{Error, ErrorKind, Read};
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+    use std::time::{Duration, SystemTime};
+
+    /// Held by tests which run the default main context, which only one thread can own at a time.
+    pub(crate) static MAIN_CONTEXT: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn verify_app() {
+        app().debug_assert();
+    }
+
+    #[test]
+    fn search_provider_paths_in_nested_nodes() {
+        let xml = r#"
+<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.freedesktop.DBus.Peer">
+  </interface>
+  <node name="code">
+    <interface name="org.gnome.Shell.SearchProvider2">
+    </interface>
+  </node>
+  <node name="custom">
+    <node name="my_code">
+      <interface name="org.freedesktop.DBus.Introspectable">
+      </interface>
+      <interface name="org.gnome.Shell.SearchProvider2">
+      </interface>
+    </node>
+  </node>
+  <node name="empty"/>
+</node>
+"#;
+        assert_eq!(
+            search_provider_paths("/de/swsnr/searchprovider/vscode", xml),
+            vec![
+                "/de/swsnr/searchprovider/vscode/code",
+                "/de/swsnr/searchprovider/vscode/custom/my_code"
+            ]
+        );
+    }
 
     #[test]
-    fn verify_app() {
-        app().debug_assert();
+    fn bus_location_objpath() {
+        let location = BusLocation {
+            busname: "de.swsnr.searchprovider.VSCodeDevel".to_string(),
+            objpath_prefix: "/de/swsnr/searchprovider/vscodedevel".to_string(),
+        };
+        assert_eq!(
+            location.objpath("arch/codeoss"),
+            "/de/swsnr/searchprovider/vscodedevel/arch/codeoss"
+        );
     }
 
     #[test]
@@ -455,8 +2282,639 @@ mod tests {
         );
     }
 
+    #[test]
+    fn read_recent_workspaces_with_comments() {
+        let data: &[u8] = include_bytes!("tests/code_1_55_storage_with_comments.json");
+        let storage = Storage::read(data).unwrap();
+        assert_eq!(
+            storage.into_workspace_urls(),
+            vec![
+                "file:///home/foo//workspace.code-workspace",
+                "file:///home/foo//mdcat",
+                "file:///home/foo//gnome-jetbrains-search-provider",
+                "file:///home/foo//gnome-shell",
+            ]
+        );
+    }
+
+    #[test]
+    fn strip_jsonc_keeps_strings() {
+        let data = br#"{"a": "// not a comment, /* nor this */", "b": "\\", "c": "\" // x"}"#;
+        assert_eq!(
+            strip_jsonc(data),
+            br#"{"a": "// not a comment, /* nor this */", "b": "\\", "c": "\" // x"}"#
+        );
+    }
+
+    /// A reader which always fails with permission denied.
+    struct PermissionDeniedReader;
+
+    impl Read for PermissionDeniedReader {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(Error::new(ErrorKind::PermissionDenied, "Permission denied"))
+        }
+    }
+
+    #[test]
+    fn read_recent_workspaces_code_1_64_state_db() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests")
+            .join("code_1_64_state.vscdb");
+        let storage = Storage::from_state_db(&path, None).unwrap();
+        assert_eq!(
+            storage.into_workspace_urls(),
+            vec![
+                "file:///home/foo/dev/mdcat",
+                "file:///home/foo/dev/workspace.code-workspace",
+                "vscode-remote://ssh-remote%2Bfoo/home/foo/dev/gnome-shell",
+            ]
+        )
+    }
+
+    #[test]
+    fn read_state_db_snapshot() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests")
+            .join("code_1_64_state.vscdb");
+        let storage = Storage::from_state_db_snapshot(&path, None).unwrap();
+        assert_eq!(
+            storage.into_workspace_urls(),
+            vec![
+                "file:///home/foo/dev/mdcat",
+                "file:///home/foo/dev/workspace.code-workspace",
+                "vscode-remote://ssh-remote%2Bfoo/home/foo/dev/gnome-shell",
+            ]
+        )
+    }
+
+    #[test]
+    fn read_state_db_checks_readability() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests")
+            .join("missing_state.vscdb");
+        let error = Storage::from_state_db(&path, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Failed to read storage data from {}", path.display())
+        );
+        assert!(error.chain().any(|cause| cause
+            .downcast_ref::<std::io::Error>()
+            .map_or(false, |error| error.kind() == ErrorKind::NotFound)));
+    }
+
+    #[test]
+    fn read_storage_file_by_its_contents() {
+        let tests = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests");
+        assert_eq!(
+            Storage::from_file(&tests.join("code_1_64_state.vscdb"))
+                .unwrap()
+                .into_workspace_urls(),
+            vec![
+                "file:///home/foo/dev/mdcat",
+                "file:///home/foo/dev/workspace.code-workspace",
+                "vscode-remote://ssh-remote%2Bfoo/home/foo/dev/gnome-shell",
+            ]
+        );
+        assert!(!Storage::from_file(&tests.join("code_1_55_storage.json"))
+            .unwrap()
+            .into_workspace_urls()
+            .is_empty());
+    }
+
+    #[test]
+    fn read_recent_workspaces_from_locked_state_db() {
+        let dir = std::env::temp_dir().join(format!(
+            "gnome-search-providers-vscode-test-locked-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.vscdb");
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("src")
+                .join("tests")
+                .join("code_1_64_state.vscdb"),
+            &path,
+        )
+        .unwrap();
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        connection
+            .execute_batch("PRAGMA locking_mode = EXCLUSIVE; BEGIN EXCLUSIVE;")
+            .unwrap();
+        let storage = Storage::from_state_db(&path, None);
+        drop(connection);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            storage.unwrap().into_workspace_urls(),
+            vec![
+                "file:///home/foo/dev/mdcat",
+                "file:///home/foo/dev/workspace.code-workspace",
+                "vscode-remote://ssh-remote%2Bfoo/home/foo/dev/gnome-shell",
+            ]
+        )
+    }
+
+    #[test]
+    fn read_recent_workspaces_from_state_db_with_max_size() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests")
+            .join("code_1_64_state.vscdb");
+        assert!(Storage::from_state_db(&path, Some(1024 * 1024)).is_ok());
+        let error = Storage::from_state_db(&path, Some(16)).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("more than the maximum of 16 bytes"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[test]
+    fn state_db_paths_with_profiles() {
+        let config_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests")
+            .join("profiles");
+        assert_eq!(
+            WorkspaceSource::state_db_paths(&config_dir),
+            vec![
+                config_dir.join("User/globalStorage/state.vscdb"),
+                config_dir.join("User/profiles/-4c1b0f7e/globalStorage/state.vscdb"),
+            ]
+        );
+        assert!(WorkspaceSource::state_db_paths(&config_dir.join("does-not-exist")).is_empty());
+    }
+
+    #[test]
+    fn stamps_with_profiles() {
+        let config_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests")
+            .join("profiles");
+        let stamps = WorkspaceSource::stamps(&config_dir)
+            .into_iter()
+            .map(|(path, stamp)| (path, stamp.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stamps,
+            vec![
+                (config_dir.join("storage.json"), false),
+                (config_dir.join("storage.json.backup"), false),
+                (config_dir.join("User/globalStorage/state.vscdb"), true),
+                (config_dir.join("User/globalStorage/state.vscdb-wal"), false),
+                (
+                    config_dir.join("User/profiles/-4c1b0f7e/globalStorage/state.vscdb"),
+                    true
+                ),
+                (
+                    config_dir.join("User/profiles/-4c1b0f7e/globalStorage/state.vscdb-wal"),
+                    false
+                ),
+                (config_dir.join("User/workspaceStorage"), false),
+            ]
+        );
+        assert_eq!(
+            WorkspaceSource::stamps(&config_dir),
+            WorkspaceSource::stamps(&config_dir)
+        );
+    }
+
+    #[test]
+    fn source_dirs_with_profiles() {
+        let config_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests")
+            .join("profiles");
+        assert_eq!(
+            WorkspaceSource::source_dirs(&config_dir),
+            vec![
+                (config_dir.clone(), Some("storage.json")),
+                (config_dir.join("User/globalStorage"), Some("state.vscdb")),
+                (
+                    config_dir.join("User/profiles/-4c1b0f7e/globalStorage"),
+                    Some("state.vscdb")
+                ),
+                (config_dir.join("User/profiles"), None),
+                (config_dir.join("User/workspaceStorage"), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn recover_workspaces_from_workspace_storage() {
+        let config_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests")
+            .join("workspace-storage");
+        let source = WorkspaceSource::from_workspace_storage(&config_dir).unwrap();
+        assert_eq!(
+            source.path,
+            config_dir.join("User").join("workspaceStorage")
+        );
+        let mut urls = source.storage.into_workspace_urls();
+        urls.sort();
+        assert_eq!(
+            urls,
+            vec![
+                "file:///home/foo/dev/mdcat",
+                "file:///home/foo/dev/workspace.code-workspace",
+            ]
+        );
+        assert!(WorkspaceSource::from_workspace_storage(&config_dir.join("User")).is_none());
+    }
+
+    #[test]
+    fn empty_storage() {
+        let data: &[u8] = br#"{"openedPathsList": {"entries": []}}"#;
+        assert!(Storage::read(data).unwrap().is_empty());
+        let data: &[u8] = include_bytes!("tests/code_1_55_storage.json");
+        assert!(!Storage::read(data).unwrap().is_empty());
+    }
+
+    #[test]
+    fn merge_workspace_sources_most_recent_first() {
+        let older: &[u8] = br#"{"openedPathsList": {"entries": [
+            {"folderUri": "file:///home/foo/a"},
+            {"folderUri": "file:///home/foo/b"}
+        ]}}"#;
+        let newer: &[u8] = br#"{"openedPathsList": {"entries": [
+            {"folderUri": "file:///home/foo/b"},
+            {"fileUri": "file:///home/foo/c/README.md"},
+            {"folderUri": "file:///home/foo/c", "label": "C"}
+        ]}}"#;
+        let sources = vec![
+            WorkspaceSource {
+                path: PathBuf::from("storage.json"),
+                modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(10)),
+                storage: Storage::read(older).unwrap(),
+            },
+            WorkspaceSource {
+                path: PathBuf::from("state.vscdb"),
+                modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(20)),
+                storage: Storage::read(newer).unwrap(),
+            },
+        ];
+        assert_eq!(
+            WorkspaceSource::merge_recent_entries(sources),
+            vec![
+                RecentUrl::Workspace("file:///home/foo/b".into()).into(),
+                RecentUrl::File("file:///home/foo/c/README.md".into()).into(),
+                RecentEntry {
+                    url: RecentUrl::Workspace("file:///home/foo/c".into()),
+                    label: Some("C".into())
+                },
+                RecentUrl::Workspace("file:///home/foo/a".into()).into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_storage_permission_denied() {
+        let error = Storage::read(PermissionDeniedReader).unwrap_err();
+        assert!(is_permission_denied(&error), "{:#}", error);
+    }
+
+    #[test]
+    fn storage_read_error_tells_how_to_fix_permissions() {
+        let path = Path::new("/home/foo/.config/Code/User/globalStorage/storage.json");
+        let error = storage_read_error(
+            glib::Error::new(gio::IOErrorEnum::PermissionDenied, "Permission denied").into(),
+            path,
+        );
+        assert!(is_permission_denied(&error), "{:#}", error);
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Not allowed to read storage data from {}; make sure the file is owned by and readable for user {}",
+                path.display(),
+                glib::user_name().to_string_lossy()
+            )
+        );
+        let error = storage_read_error(Error::new(ErrorKind::NotFound, "Not found").into(), path);
+        assert_eq!(
+            error.to_string(),
+            format!("Failed to read storage data from {}", path.display())
+        );
+    }
+
+    #[test]
+    fn read_argv_with_user_data_dir() {
+        let data: &[u8] = include_bytes!("tests/argv.json");
+        let argv = Argv::read(data).unwrap();
+        assert_eq!(argv.user_data_dir, Some(PathBuf::from("/data/foo/vscode")));
+    }
+
+    fn base_dirs(home_dir: &Path) -> BaseDirs {
+        BaseDirs {
+            home_dir: home_dir.to_path_buf(),
+            user_config_dir: PathBuf::from("/home/foo/.config"),
+            portable_dir: None,
+        }
+    }
+
+    #[test]
+    fn config_dir_from_argv() {
+        let home_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let location = ConfigLocation {
+            dirnames: &["Code"],
+            argv_dirname: "tests",
+            bases: &[ConfigBase::Xdg],
+        };
+        assert_eq!(
+            location.find_config_dir(&base_dirs(&home_dir)),
+            Path::new("/data/foo/vscode")
+        );
+    }
+
+    #[test]
+    fn config_dir_without_argv() {
+        let location = ConfigLocation {
+            dirnames: &["Code"],
+            argv_dirname: ".vscode-does-not-exist",
+            bases: &[
+                ConfigBase::Xdg,
+                ConfigBase::Flatpak("com.visualstudio.code"),
+            ],
+        };
+        assert_eq!(
+            location.find_config_dir(&base_dirs(Path::new(env!("CARGO_MANIFEST_DIR")))),
+            Path::new("/home/foo/.config/Code")
+        );
+    }
+
+    #[test]
+    fn config_dir_portable_next_to_executable() {
+        let tests_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests");
+        let location = ConfigLocation {
+            dirnames: &["Code"],
+            argv_dirname: ".vscode-does-not-exist",
+            bases: &[ConfigBase::Xdg, ConfigBase::Portable],
+        };
+        let dirs = base_dirs(&tests_dir).for_executable(&tests_dir.join("portable").join("code"));
+        assert_eq!(
+            location.find_config_dir(&dirs),
+            tests_dir.join("portable").join("data").join("user-data")
+        );
+        let dirs = base_dirs(&tests_dir).for_executable(&tests_dir.join("code"));
+        assert_eq!(dirs.portable_dir, None);
+    }
+
+    #[test]
+    fn config_dir_first_existing_base() {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let dirs = BaseDirs {
+            portable_dir: Some(manifest_dir.join("does-not-exist")),
+            ..base_dirs(manifest_dir)
+        };
+        let location = ConfigLocation {
+            dirnames: &["src"],
+            argv_dirname: ".vscode-does-not-exist",
+            bases: &[
+                ConfigBase::Portable,
+                ConfigBase::Snap("code"),
+                ConfigBase::Flatpak("com.visualstudio.code"),
+                ConfigBase::Xdg,
+            ],
+        };
+        assert_eq!(
+            location.find_config_dir(&dirs),
+            manifest_dir.join("does-not-exist").join("user-data")
+        );
+        let dirs = BaseDirs {
+            home_dir: manifest_dir.join("does-not-exist"),
+            user_config_dir: manifest_dir.to_path_buf(),
+            portable_dir: None,
+        };
+        assert_eq!(location.find_config_dir(&dirs), manifest_dir.join("src"));
+    }
+
+    #[test]
+    fn config_dir_first_existing_dirname() {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let dirs = BaseDirs {
+            home_dir: manifest_dir.join("does-not-exist"),
+            user_config_dir: manifest_dir.to_path_buf(),
+            portable_dir: None,
+        };
+        let location = ConfigLocation {
+            dirnames: &["does-not-exist", "src"],
+            argv_dirname: ".vscode-does-not-exist",
+            bases: &[
+                ConfigBase::Flatpak("com.visualstudio.code"),
+                ConfigBase::Xdg,
+            ],
+        };
+        assert_eq!(location.find_config_dir(&dirs), manifest_dir.join("src"));
+        let location = ConfigLocation {
+            dirnames: &["does-not-exist", "does-not-exist-either"],
+            ..location
+        };
+        assert_eq!(
+            location.find_config_dir(&dirs),
+            manifest_dir
+                .join("does-not-exist")
+                .join(".var/app/com.visualstudio.code/config/does-not-exist")
+        );
+    }
+
+    #[test]
+    fn provider_config_dir_from_config() {
+        let definition = ProviderDefinition {
+            label: "Test",
+            desktop_ids: &["gnome-search-providers-vscode-test.desktop"],
+            executables: &[],
+            relative_obj_path: "test",
+            config: ConfigLocation {
+                dirnames: &["Code"],
+                argv_dirname: ".vscode-does-not-exist",
+                bases: &[ConfigBase::Xdg],
+            },
+        };
+        assert_eq!(definition.key(), "gnome-search-providers-vscode-test");
+        assert_eq!(
+            definition.config_dir_variable(),
+            "GNOME_SEARCH_PROVIDER_GNOME_SEARCH_PROVIDERS_VSCODE_TEST_CONFIG_DIR"
+        );
+
+        let dirs = base_dirs(Path::new("/home/foo"));
+        assert_eq!(
+            definition.find_config_dir(&Config::default(), &dirs),
+            PathBuf::from("/home/foo/.config/Code")
+        );
+        let config = Config::parse(
+            "[providers.gnome-search-providers-vscode-test]\nconfig-dir = \".code\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            definition.find_config_dir(&config, &dirs),
+            PathBuf::from("/home/foo/.code")
+        );
+        let config = Config::parse(
+            "[providers.gnome-search-providers-vscode-test]\nconfig-dir = \"/opt/code\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            definition.find_config_dir(&config, &dirs),
+            PathBuf::from("/opt/code")
+        );
+    }
+
+    #[test]
+    fn find_storage_dirs_in_tests() {
+        let tests_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests");
+        assert_eq!(
+            find_storage_dirs(&tests_dir),
+            vec![tests_dir.join("profiles")]
+        );
+        assert!(find_storage_dirs(&tests_dir.join("does-not-exist")).is_empty());
+    }
+
+    #[test]
+    fn product_for_executable() {
+        let fork_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests")
+            .join("fork");
+        let (path, product) =
+            Product::find_for_executable(&fork_dir.join("bin").join("fork-code")).unwrap();
+        assert_eq!(path, fork_dir.join("resources/app/product.json"));
+        assert_eq!(product.name_short, "Fork Code");
+        assert_eq!(product.name_long.as_deref(), Some("Fork Code Editor"));
+        assert!(Product::find_for_executable(Path::new("/does-not-exist/bin/code")).is_none());
+
+        let nix_dir = fork_dir.parent().unwrap().join("nix");
+        let (path, product) =
+            Product::find_for_executable(&nix_dir.join("bin").join("codium")).unwrap();
+        assert_eq!(
+            path,
+            nix_dir.join("lib/vscodium/resources/app/product.json")
+        );
+        assert_eq!(product.name_short, "VSCodium");
+    }
+
+    #[test]
+    fn extra_config_dirs_in_roots() {
+        let tests_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("tests");
+        let config_dir = PathBuf::from("/home/foo/.config/profiles");
+        assert_eq!(
+            with_extra_config_dirs(
+                config_dir.clone(),
+                &["does-not-exist", "profiles"],
+                &[
+                    tests_dir.clone(),
+                    tests_dir.join("does-not-exist"),
+                    tests_dir.clone()
+                ]
+            ),
+            vec![config_dir.clone(), tests_dir.join("profiles")]
+        );
+        assert_eq!(
+            with_extra_config_dirs(config_dir.clone(), &["profiles"], &[]),
+            vec![config_dir]
+        );
+    }
+
+    #[test]
+    fn match_config_dir_by_name() {
+        let candidates = vec![
+            PathBuf::from("/home/foo/.config/Fork Code"),
+            PathBuf::from("/home/foo/.config/Code - OSS"),
+        ];
+        let names = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            match_config_dir(&names(&["fork-code"]), &candidates),
+            Some(0)
+        );
+        assert_eq!(
+            match_config_dir(&names(&["flatpak", "code-oss"]), &candidates),
+            Some(1)
+        );
+        assert_eq!(
+            match_config_dir(&names(&["Code - OSS", "fork-code"]), &candidates),
+            Some(1)
+        );
+        assert_eq!(match_config_dir(&names(&["", "-"]), &candidates), None);
+        assert_eq!(match_config_dir(&names(&["code"]), &candidates), None);
+    }
+
+    #[test]
+    fn detected_relative_obj_path_is_valid() {
+        assert_eq!(
+            detected_relative_obj_path("com.example.Fork-Code.desktop"),
+            "detected/com_example_Fork_Code"
+        );
+    }
+
+    #[test]
+    fn read_storage_skips_unexpected_entries() {
+        let data: &[u8] = br#"{
+            "openedPathsList": {
+                "workspaces3": [42, "file:///home/foo/dev/old"],
+                "entries": [
+                    {"folderUri": "file:///home/foo/dev/mdcat", "label": 42},
+                    {"folderUri": 42},
+                    "garbage",
+                    {"workspace": {"id": "abc", "configPath": "file:///home/foo/dev/workspace.code-workspace"}, "label": "Work"}
+                ]
+            }
+        }"#;
+        let entries = Storage::read(data).unwrap().into_recent_entries();
+        assert_eq!(
+            entries,
+            vec![
+                RecentUrl::Workspace("file:///home/foo/dev/mdcat".to_string()).into(),
+                RecentEntry {
+                    url: RecentUrl::Workspace(
+                        "file:///home/foo/dev/workspace.code-workspace".to_string()
+                    ),
+                    label: Some("Work".to_string())
+                },
+                RecentUrl::Workspace("file:///home/foo/dev/old".to_string()).into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_storage_ignores_unexpected_lists() {
+        let data: &[u8] = br#"{"openedPathsList": {"entries": {"folderUri": "file:///a"}, "workspaces3": ["file:///b"]}}"#;
+        assert_eq!(
+            Storage::read(data).unwrap().into_workspace_urls(),
+            vec!["file:///b"]
+        );
+        let data: &[u8] = br#"{"openedPathsList": "garbage"}"#;
+        assert!(Storage::read(data).unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_storage_invalid_json_is_not_permission_denied() {
+        let data: &[u8] = b"{ invalid";
+        let error = Storage::read(data).unwrap_err();
+        assert!(!is_permission_denied(&error), "{:#}", error);
+    }
+
+    #[test]
+    fn read_storage_truncated_json() {
+        let data: &[u8] = br#"{"openedPathsList": {"workspaces3": ["#;
+        assert!(is_truncated(&Storage::read(data).unwrap_err()));
+        let data: &[u8] = b"{ invalid";
+        assert!(!is_truncated(&Storage::read(data).unwrap_err()));
+    }
+
     mod providers {
-        use crate::{BUSNAME, PROVIDERS};
+        use crate::{BusLocation, BUSNAME, COMBINED_RELATIVE_OBJ_PATH, PROVIDERS};
         use anyhow::{Context, Result};
         use ini::Ini;
         use std::collections::HashSet;
@@ -469,38 +2927,40 @@ mod tests {
             version: String,
         }
 
+        fn load_provider_file(filepath: &Path) -> Result<ProviderFile> {
+            let ini = Ini::load_from_file(filepath)
+                .with_context(|| format!("Failed to parse ini file at {}", filepath.display()))?;
+            Ok(ProviderFile {
+                desktop_id: ini
+                    .get_from(Some("Shell Search Provider"), "DesktopId")
+                    .with_context(|| format!("DesktopId missing in {}", &filepath.display()))?
+                    .to_string(),
+                object_path: ini
+                    .get_from(Some("Shell Search Provider"), "ObjectPath")
+                    .with_context(|| format!("ObjectPath missing in {}", &filepath.display()))?
+                    .to_string(),
+                bus_name: ini
+                    .get_from(Some("Shell Search Provider"), "BusName")
+                    .with_context(|| format!("BusName missing in {}", &filepath.display()))?
+                    .to_string(),
+                version: ini
+                    .get_from(Some("Shell Search Provider"), "Version")
+                    .with_context(|| format!("Version missing in {}", &filepath.display()))?
+                    .to_string(),
+            })
+        }
+
         fn load_all_provider_files() -> Result<Vec<ProviderFile>> {
             let mut providers = Vec::new();
             let ini_files = globwalk::GlobWalkerBuilder::new(
-                Path::new(env!("CARGO_MANIFEST_DIR")).join("providers"),
+                Path::new(env!("OUT_DIR")).join("providers"),
                 "*.ini",
             )
+            .max_depth(1)
             .build()
             .unwrap();
             for entry in ini_files {
-                let filepath = entry.unwrap().into_path();
-                let ini = Ini::load_from_file(&filepath).with_context(|| {
-                    format!("Failed to parse ini file at {}", filepath.display())
-                })?;
-                let provider = ProviderFile {
-                    desktop_id: ini
-                        .get_from(Some("Shell Search Provider"), "DesktopId")
-                        .with_context(|| format!("DesktopId missing in {}", &filepath.display()))?
-                        .to_string(),
-                    object_path: ini
-                        .get_from(Some("Shell Search Provider"), "ObjectPath")
-                        .with_context(|| format!("ObjectPath missing in {}", &filepath.display()))?
-                        .to_string(),
-                    bus_name: ini
-                        .get_from(Some("Shell Search Provider"), "BusName")
-                        .with_context(|| format!("BusName missing in {}", &filepath.display()))?
-                        .to_string(),
-                    version: ini
-                        .get_from(Some("Shell Search Provider"), "Version")
-                        .with_context(|| format!("Version missing in {}", &filepath.display()))?
-                        .to_string(),
-                };
-                providers.push(provider);
+                providers.push(load_provider_file(entry.unwrap().path())?);
             }
 
             Ok(providers)
@@ -510,44 +2970,72 @@ mod tests {
         fn all_providers_have_a_correct_ini_file() {
             let provider_files = load_all_provider_files().unwrap();
             for provider in PROVIDERS {
-                let provider_file = provider_files
-                    .iter()
-                    .find(|p| p.desktop_id == provider.desktop_id);
-                assert!(
-                    provider_file.is_some(),
-                    "Provider INI missing for provider {} with desktop ID {}",
-                    provider.label,
-                    provider.desktop_id
-                );
+                for desktop_id in provider.desktop_ids {
+                    let provider_file = provider_files.iter().find(|p| p.desktop_id == *desktop_id);
+                    assert!(
+                        provider_file.is_some(),
+                        "Provider INI missing for provider {} with desktop ID {}",
+                        provider.label,
+                        desktop_id
+                    );
 
-                assert_eq!(provider_file.unwrap().object_path, provider.objpath());
-                assert_eq!(provider_file.unwrap().bus_name, BUSNAME);
-                assert_eq!(provider_file.unwrap().version, "2");
+                    assert_eq!(
+                        provider_file.unwrap().object_path,
+                        BusLocation::default().objpath(provider.relative_obj_path)
+                    );
+                    assert_eq!(provider_file.unwrap().bus_name, BUSNAME);
+                    assert_eq!(provider_file.unwrap().version, "2");
+                }
             }
         }
 
+        #[test]
+        fn combined_provider_has_a_correct_ini_file() {
+            let provider_file = load_provider_file(
+                &Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("providers")
+                    .join("combined")
+                    .join("de.swsnr.searchprovider.vscode.combined.ini"),
+            )
+            .unwrap();
+            assert_eq!(provider_file.desktop_id, "{DESKTOP_ID}");
+            assert_eq!(
+                provider_file.object_path,
+                BusLocation::default().objpath(COMBINED_RELATIVE_OBJ_PATH)
+            );
+            assert_eq!(provider_file.bus_name, BUSNAME);
+            assert_eq!(provider_file.version, "2");
+        }
+
         #[test]
         fn no_extra_ini_files_without_providers() {
             let provider_files = load_all_provider_files().unwrap();
-            assert_eq!(PROVIDERS.len(), provider_files.len());
+            let desktop_ids = PROVIDERS.iter().map(|p| p.desktop_ids.len()).sum::<usize>();
+            assert_eq!(desktop_ids, provider_files.len());
         }
 
         #[test]
         fn desktop_ids_are_unique() {
             let mut ids = HashSet::new();
             for provider in PROVIDERS {
-                ids.insert(provider.desktop_id);
+                for desktop_id in provider.desktop_ids {
+                    assert!(
+                        ids.insert(desktop_id),
+                        "Duplicate desktop ID {}",
+                        desktop_id
+                    );
+                }
             }
-            assert_eq!(PROVIDERS.len(), ids.len());
         }
 
         #[test]
         fn dbus_paths_are_unique() {
             let mut paths = HashSet::new();
             for provider in PROVIDERS {
-                paths.insert(provider.objpath());
+                paths.insert(BusLocation::default().objpath(provider.relative_obj_path));
             }
-            assert_eq!(PROVIDERS.len(), paths.len());
+            paths.insert(BusLocation::default().objpath(COMBINED_RELATIVE_OBJ_PATH));
+            assert_eq!(PROVIDERS.len() + 1, paths.len());
         }
     }
 }
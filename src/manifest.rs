@@ -0,0 +1,214 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Read the names and languages of projects from their manifests.
+
+use std::io::ErrorKind;
+use std::path::Path;
+
+use tracing::{debug, trace};
+
+/// Skip manifests larger than this many bytes.
+///
+/// Real manifests are much smaller; this only protects against reading huge files
+/// which happen to have the name of a manifest.
+const MAX_MANIFEST_SIZE: u64 = 1024 * 1024;
+
+/// Get the name of a package from `Cargo.toml` `data`.
+fn cargo_name(data: &str) -> Option<String> {
+    let manifest = toml::from_str::<toml::Value>(data).ok()?;
+    manifest
+        .get("package")?
+        .get("name")?
+        .as_str()
+        .map(ToString::to_string)
+}
+
+/// Get the name of a package from `package.json` `data`.
+fn npm_name(data: &str) -> Option<String> {
+    let manifest = serde_json::from_str::<serde_json::Value>(data).ok()?;
+    manifest.get("name")?.as_str().map(ToString::to_string)
+}
+
+/// Get the name of a project from `pyproject.toml` `data`.
+///
+/// Take the standard `project.name`, and fall back to Poetry's `tool.poetry.name`.
+fn pyproject_name(data: &str) -> Option<String> {
+    let manifest = toml::from_str::<toml::Value>(data).ok()?;
+    manifest
+        .get("project")
+        .and_then(|project| project.get("name"))
+        .or_else(|| manifest.get("tool")?.get("poetry")?.get("name"))?
+        .as_str()
+        .map(ToString::to_string)
+}
+
+/// Get the module path from `go.mod` `data`.
+fn go_module(data: &str) -> Option<String> {
+    data.lines()
+        .find_map(|line| line.trim().strip_prefix("module "))
+        .map(|module| module.trim().trim_matches('"').to_string())
+        .filter(|module| !module.is_empty())
+}
+
+/// Manifests to read projects from, with the language of the project and the function
+/// to get the name from the manifest, in order of precedence.
+const MANIFESTS: [(&str, &str, fn(&str) -> Option<String>); 4] = [
+    ("Cargo.toml", "rust", cargo_name),
+    ("package.json", "javascript", npm_name),
+    ("pyproject.toml", "python", pyproject_name),
+    ("go.mod", "go", go_module),
+];
+
+/// Get the language of [`MANIFESTS`] which `language` refers to.
+///
+/// Resolve common abbreviations and related languages, e.g. `rs` to `rust`, and `ts`
+/// to `javascript`; return any other `language` as is.
+pub fn canonical_language(language: &str) -> &str {
+    match language {
+        "rs" => "rust",
+        "js" | "node" | "ts" | "typescript" => "javascript",
+        "py" => "python",
+        "golang" => "go",
+        _ => language,
+    }
+}
+
+/// A project in a workspace.
+#[derive(Debug, PartialEq)]
+pub struct Project {
+    /// The name of the project, if its manifest declares a name.
+    pub name: Option<String>,
+    /// The language of the project, after its manifest.
+    pub language: &'static str,
+}
+
+/// Read the manifest at `path`.
+///
+/// Return `None` if the manifest doesn't exist, is too large or can't be read.
+fn read_manifest(path: &Path) -> Option<String> {
+    let size = match std::fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(error) if error.kind() == ErrorKind::NotFound => return None,
+        Err(error) => {
+            debug!("Failed to read {}: {}", path.display(), error);
+            return None;
+        }
+    };
+    if MAX_MANIFEST_SIZE < size {
+        debug!("Skipping {}, too large ({} bytes)", path.display(), size);
+        return None;
+    }
+    std::fs::read_to_string(path)
+        .map_err(|error| debug!("Failed to read {}: {}", path.display(), error))
+        .ok()
+}
+
+/// Read the project in `dir` from its manifests.
+///
+/// Try `Cargo.toml`, `package.json`, `pyproject.toml` and `go.mod` in this order.  Take
+/// the language from the first manifest which exists, and the name from the first
+/// manifest which declares a name; a manifest may have no name, e.g. the `Cargo.toml`
+/// of a Cargo workspace without a package.
+///
+/// Return `None` if `dir` has none of these manifests.
+pub fn read_project(dir: &Path) -> Option<Project> {
+    let mut project: Option<Project> = None;
+    for (file_name, language, name) in MANIFESTS {
+        let path = dir.join(file_name);
+        if let Some(data) = read_manifest(&path) {
+            let name = name(&data).filter(|name| !name.is_empty());
+            trace!(
+                "Found {} project {:?} in {}",
+                language,
+                name,
+                path.display()
+            );
+            let project = project.get_or_insert(Project {
+                name: None,
+                language,
+            });
+            if project.name.is_none() {
+                project.name = name;
+            }
+        }
+    }
+    project
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_names() {
+        assert_eq!(
+            cargo_name("[package]\nname = \"mdcat\"\nversion = \"1.0.0\"\n").as_deref(),
+            Some("mdcat")
+        );
+        assert_eq!(cargo_name("[workspace]\nmembers = [\"foo\"]\n"), None);
+        assert_eq!(
+            npm_name(r#"{"name": "@foo/bar", "version": "1.0.0"}"#).as_deref(),
+            Some("@foo/bar")
+        );
+        assert_eq!(npm_name("not json"), None);
+        assert_eq!(
+            pyproject_name("[project]\nname = \"spam\"\n").as_deref(),
+            Some("spam")
+        );
+        assert_eq!(
+            pyproject_name("[tool.poetry]\nname = \"eggs\"\n").as_deref(),
+            Some("eggs")
+        );
+        assert_eq!(
+            go_module("// comment\nmodule github.com/foo/bar\n\ngo 1.17\n").as_deref(),
+            Some("github.com/foo/bar")
+        );
+    }
+
+    #[test]
+    fn canonical_language_resolves_aliases() {
+        assert_eq!(canonical_language("rs"), "rust");
+        assert_eq!(canonical_language("ts"), "javascript");
+        assert_eq!(canonical_language("python"), "python");
+        assert_eq!(canonical_language("haskell"), "haskell");
+    }
+
+    #[test]
+    fn read_project_reads_first_manifest() {
+        let dir = std::env::temp_dir().join(format!(
+            "gnome-search-providers-vscode-test-manifest-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(read_project(&dir), None);
+        std::fs::write(dir.join("package.json"), r#"{"name": "web-ui"}"#).unwrap();
+        assert_eq!(
+            read_project(&dir),
+            Some(Project {
+                name: Some("web-ui".into()),
+                language: "javascript"
+            })
+        );
+        std::fs::write(dir.join("Cargo.toml"), "[workspace]\n").unwrap();
+        assert_eq!(
+            read_project(&dir),
+            Some(Project {
+                name: Some("web-ui".into()),
+                language: "rust"
+            })
+        );
+        std::fs::write(dir.join("Cargo.toml"), "[package]\nname = \"server\"\n").unwrap();
+        assert_eq!(
+            read_project(&dir),
+            Some(Project {
+                name: Some("server".into()),
+                language: "rust"
+            })
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,3516 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The search provider DBus interface for recent VSCode workspaces.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use caseless::Caseless;
+use deunicode::deunicode;
+use globset::GlobSet;
+use indexmap::IndexMap;
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+use tracing::{debug, error, info, instrument, trace, Span};
+use tracing_futures::Instrument;
+use unicode_normalization::char::{decompose_compatible, is_combining_mark};
+use url::Url;
+use zbus::dbus_interface;
+use zbus::zvariant::Value;
+
+use gnome_search_provider_common::app::AppLaunchClient;
+use gnome_search_provider_common::futures_channel;
+use gnome_search_provider_common::gio;
+use gnome_search_provider_common::gio::glib;
+
+use crate::frecency::Frecency;
+use crate::idle::ActivityTracker;
+use crate::manifest::{canonical_language, read_project};
+use crate::template::{format_age, DescriptionTemplate, Placeholder};
+use crate::{is_permission_denied, FileStamp, RecentUrl, WorkspaceSource};
+
+/// Counts changes to the sources of recent workspaces of an app.
+///
+/// Clones share the same count.
+#[derive(Debug, Clone, Default)]
+pub struct StorageChanges(Arc<AtomicUsize>);
+
+impl StorageChanges {
+    /// Record a change.
+    pub fn bump(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// The number of changes recorded so far.
+    fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// An app whose recent workspaces a search provider exposes.
+#[derive(Debug, Clone)]
+pub struct ProviderApp {
+    /// A human readable label for the app.
+    pub label: String,
+    /// The desktop ID of the app.
+    pub desktop_id: String,
+    /// The object path of the search provider for just this app.
+    ///
+    /// Identifies workspaces of this app, also in the combined provider.
+    pub objpath: String,
+    /// The icon of the app, serialized as string.
+    pub icon: Option<String>,
+    /// The configuration directories of the app.
+    ///
+    /// The first directory is the main configuration directory of the app; further
+    /// directories come from extra configuration roots.
+    pub config_dirs: Vec<PathBuf>,
+    /// The command to run the app with, to launch remote workspaces.
+    pub command: Vec<OsString>,
+    /// The URL of a VSCode server, e.g. code-server, which serves the workspaces of this app.
+    ///
+    /// If set, open workspaces at this server with the app, instead of opening
+    /// them in the app directly.
+    pub server_url: Option<String>,
+    /// Changes to the sources of recent workspaces of the app.
+    ///
+    /// `None` if we don't watch the sources; we then read them on every search.
+    pub changes: Option<StorageChanges>,
+}
+
+/// A recent workspace of a VSCode variant.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RecentWorkspace {
+    /// The human readable name.
+    pub name: String,
+    /// The workspace URL.
+    pub url: String,
+    /// A human readable description of the workspace location.
+    pub description: String,
+    /// The desktop ID of the app this workspace belongs to.
+    pub app_id: String,
+    /// Whether this is a recently opened file rather than a workspace.
+    pub is_file: bool,
+    /// The local path of the workspace, for `file://` URLs.
+    ///
+    /// In contrast to `description` this path has the exact bytes of the path, even
+    /// if they aren't valid UTF-8.
+    pub path: Option<PathBuf>,
+    /// The location of a remote workspace, e.g. the SSH host or the name of the tunnel.
+    ///
+    /// See [`remote_location`].
+    pub host: Option<String>,
+    /// The name of the project in a local workspace, from its manifest.
+    ///
+    /// See [`add_projects`].
+    pub project: Option<String>,
+    /// The language of the project in a local workspace, from its manifest.
+    ///
+    /// See [`add_projects`].
+    pub language: Option<&'static str>,
+    /// The texts to match search terms against.
+    ///
+    /// Compute these anew after changing the name, the URL, the project or the host.
+    pub keys: SearchKeys,
+}
+
+/// Read all sources of recent workspaces in `config_dir`.
+///
+/// Move to the main thread and then asynchronously read the storage through Gio,
+/// and get it sent back to us via a oneshot channel.  We can't run the future
+/// right away, because Gio futures aren't Send.
+async fn read_workspace_sources(
+    config_dir: PathBuf,
+    max_size: Option<u64>,
+) -> Result<Vec<WorkspaceSource>> {
+    let span = Span::current();
+    let (send, recv) = futures_channel::oneshot::channel();
+    glib::MainContext::default().invoke(move || {
+        glib::MainContext::default().spawn_local(
+            async move {
+                send.send(WorkspaceSource::read_all(config_dir, max_size).await)
+                    .unwrap()
+            }
+            .instrument(span),
+        );
+    });
+    recv.await.unwrap()
+}
+
+/// Read all sources of recent workspaces in all configuration directories of `app`.
+///
+/// Only fail if no directory could be read at all, with the last error.
+async fn read_app_sources(
+    app: &ProviderApp,
+    max_size: Option<u64>,
+) -> Result<Vec<WorkspaceSource>> {
+    let mut sources = Vec::new();
+    let mut last_error = None;
+    for config_dir in &app.config_dirs {
+        match read_workspace_sources(config_dir.clone(), max_size).await {
+            Ok(dir_sources) => sources.extend(dir_sources),
+            Err(error) => {
+                debug!(
+                    "Failed to read recent workspaces in {}: {:#}",
+                    config_dir.display(),
+                    error
+                );
+                last_error = Some(error);
+            }
+        }
+    }
+    match last_error {
+        Some(error) if sources.is_empty() => Err(error),
+        _ => Ok(sources),
+    }
+}
+
+/// Get the local path of `url` if it's a `file://` URL.
+///
+/// Decode the path of `url` to raw bytes, to keep paths which aren't valid UTF-8.
+fn file_url_path(url: &str) -> Option<PathBuf> {
+    let url = Url::parse(url).ok().filter(|url| url.scheme() == "file")?;
+    let bytes = percent_decode_str(url.path()).collect::<Vec<u8>>();
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+/// Get the name and the decoded path of `url` if it's a `file://` URL.
+///
+/// Ignore the authority of the URL; VSCode sometimes stores `file://localhost/…`
+/// or even `file://hostname/…` URLs for local workspaces.
+///
+/// Take the name from the last segment of the path before decoding, so that encoded
+/// slashes (`%2F`) become part of the name instead of separating segments.
+fn file_url_name_and_path(url: &str) -> Option<(String, String)> {
+    let url = Url::parse(url).ok().filter(|url| url.scheme() == "file")?;
+    let path = percent_decode(url.path());
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+        .map(percent_decode)
+        .unwrap_or_else(|| path.clone());
+    Some((name, path))
+}
+
+/// The URL scheme of remote workspaces.
+const REMOTE_SCHEME: &str = "vscode-remote";
+
+/// The URL scheme of virtual workspaces, e.g. GitHub repositories.
+const VFS_SCHEME: &str = "vscode-vfs";
+
+/// Get the local folder of a dev container from the `data` in its remote authority.
+///
+/// The authority has the hex-encoded local folder, either directly or as `hostPath`
+/// in a JSON object.
+fn dev_container_host_path(data: &str) -> Option<String> {
+    let decoded = String::from_utf8(hex::decode(data).ok()?).ok()?;
+    if decoded.starts_with('{') {
+        serde_json::from_str::<serde_json::Value>(&decoded)
+            .ok()?
+            .get("hostPath")?
+            .as_str()
+            .map(ToString::to_string)
+    } else {
+        Some(decoded)
+    }
+}
+
+/// Get the location of a remote workspace from the decoded `authority` of its URL.
+///
+/// Return the host of `ssh-remote+<host>`, the name of `tunnel+<name>` and
+/// `codespaces+<name>`, and the local folder of `dev-container+<hex>`, along with the
+/// kind of remote, i.e. the part of the authority before the `+`.
+fn remote_location(authority: &str) -> Option<(&str, String)> {
+    let (kind, data) = authority.split_once('+')?;
+    let location = match kind {
+        "ssh-remote" | "tunnel" | "codespaces" => data.to_string(),
+        "dev-container" => dev_container_host_path(data)?,
+        _ => return None,
+    };
+    Some((kind, location))
+}
+
+/// Get the location of `url` if it's a remote URL; see [`remote_location`].
+fn remote_host(url: &str) -> Option<String> {
+    let url = Url::parse(url)
+        .ok()
+        .filter(|url| url.scheme() == REMOTE_SCHEME)?;
+    let authority = percent_decode_str(url.host_str()?).decode_utf8_lossy();
+    remote_location(&authority).map(|(_, location)| location)
+}
+
+/// Get the name and a description of `url` if it's a remote SSH or dev container URL.
+///
+/// VSCode stores remote SSH workspaces as `vscode-remote://ssh-remote+<host>/<path>`
+/// URLs, with the `+` often percent-encoded; describe these as "path on host".
+///
+/// Remote tunnels have `tunnel+<name>` as authority; describe these as "path on tunnel name",
+/// and likewise codespaces, with `codespaces+<name>` as authority.
+///
+/// Dev containers have `dev-container+<hex>` as authority, with the hex-encoded local
+/// folder of the container; describe these with the local folder.
+fn remote_name_and_description(url: &str) -> Option<(String, String)> {
+    let url = Url::parse(url)
+        .ok()
+        .filter(|url| url.scheme() == REMOTE_SCHEME)?;
+    let authority = percent_decode_str(url.host_str()?).decode_utf8_lossy();
+    let path = percent_decode(url.path());
+    let (kind, location) = remote_location(&authority)?;
+    let description = match kind {
+        "ssh-remote" => format!("{} on {}", path, location),
+        "tunnel" => format!("{} on tunnel {}", path, location),
+        "codespaces" => format!("{} in codespace {}", path, location),
+        _ => format!("{} in dev container for {}", path, location),
+    };
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+        .map(percent_decode)
+        .unwrap_or(location);
+    Some((name, description))
+}
+
+/// Get the kind of location of the workspace at `url`.
+///
+/// Return `local` for `file://` URLs, the kind of remote for remote URLs, e.g. `ssh` or
+/// `dev container`, and `GitHub` for virtual GitHub repositories; fall back to the
+/// scheme of `url`.
+fn location_kind(url: &str) -> String {
+    let url = match Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return String::new(),
+    };
+    let authority = percent_decode(url.host_str().unwrap_or_default());
+    let kind = authority.split('+').next().unwrap_or_default();
+    match url.scheme() {
+        "file" => "local".to_string(),
+        REMOTE_SCHEME => match kind {
+            "ssh-remote" => "ssh",
+            "codespaces" => "codespace",
+            "dev-container" => "dev container",
+            "" => REMOTE_SCHEME,
+            kind => kind,
+        }
+        .to_string(),
+        VFS_SCHEME if kind == "github" => "GitHub".to_string(),
+        scheme => scheme.to_string(),
+    }
+}
+
+/// Get the decoded path of `url`, or the whole decoded `url` if it doesn't parse.
+fn url_path(url: &str) -> String {
+    Url::parse(url)
+        .map(|parsed| percent_decode(parsed.path()))
+        .unwrap_or_else(|_| percent_decode(url))
+}
+
+/// Get the name and a description of `url` if it's a virtual GitHub repository.
+///
+/// VSCode stores repositories opened with GitHub Repositories or in the browser as
+/// `vscode-vfs://github+<data>/<owner>/<repo>` URLs; name these after the repository
+/// and describe them as "owner/repo on GitHub".
+fn github_name_and_description(url: &str) -> Option<(String, String)> {
+    let url = Url::parse(url)
+        .ok()
+        .filter(|url| url.scheme() == VFS_SCHEME)?;
+    let authority = percent_decode_str(url.host_str()?).decode_utf8_lossy();
+    if authority != "github" && !authority.starts_with("github+") {
+        return None;
+    }
+    let segments = url
+        .path_segments()?
+        .filter(|segment| !segment.is_empty())
+        .map(percent_decode)
+        .collect::<Vec<_>>();
+    let name = segments.get(1).or_else(|| segments.first())?.clone();
+    Some((name, format!("{} on GitHub", segments.join("/"))))
+}
+
+/// Get the command line option to open a workspace or file at a remote `url`.
+///
+/// VSCode can't open remote URLs passed through its desktop file, so we need to
+/// launch remote workspaces with `--folder-uri`, and workspace files and files
+/// with `--file-uri`.  The same holds for virtual workspaces.  Return `None` for
+/// local URLs.
+fn remote_cli_option(url: &str, is_file: bool) -> Option<&'static str> {
+    let is_remote = [REMOTE_SCHEME, VFS_SCHEME]
+        .iter()
+        .any(|scheme| url.starts_with(&format!("{}:", scheme)));
+    if !is_remote {
+        None
+    } else if is_file || url.ends_with(CODE_WORKSPACE_EXTENSION) {
+        Some("--file-uri")
+    } else {
+        Some("--folder-uri")
+    }
+}
+
+/// The URL to open the workspace at `url` at the VSCode server at `server_url`.
+///
+/// code-server and openvscode-server open the folder or the workspace file at
+/// the path in the `folder` or `workspace` query parameter.  They can't open
+/// single files; return `None` for files, and for invalid URLs.
+fn server_workspace_url(server_url: &str, url: &str, is_file: bool) -> Option<String> {
+    if is_file {
+        return None;
+    }
+    let path = percent_decode(Url::parse(url).ok()?.path());
+    let parameter = if path.ends_with(CODE_WORKSPACE_EXTENSION) {
+        "workspace"
+    } else {
+        "folder"
+    };
+    let mut server_url = Url::parse(server_url).ok()?;
+    server_url.query_pairs_mut().append_pair(parameter, &path);
+    Some(server_url.into())
+}
+
+/// Decode percent-encoded characters in `s`, replacing invalid UTF-8.
+fn percent_decode(s: &str) -> String {
+    percent_decode_str(s).decode_utf8_lossy().into_owned()
+}
+
+/// Abbreviate the `home` directory at the start of the decoded `path` with `~`.
+///
+/// Return `path` as is if it's outside of `home`, or if `home` is the root directory.
+fn abbreviate_home(path: &str, home: &Path) -> String {
+    let home = match home.to_str().map(|home| home.trim_end_matches('/')) {
+        Some(home) if !home.is_empty() => home,
+        _ => return path.to_string(),
+    };
+    match path.strip_prefix(home) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("~{}", rest),
+        _ => path.to_string(),
+    }
+}
+
+/// Get a name for a workspace at `url`, if `url` isn't a `file://` URL.
+///
+/// Ignore query and fragment of `url`, and use the last non-empty, decoded segment of the path of `url`.  If the URL has no path,
+/// fall back to its scheme and authority, and as a last resort, to the whole URL.
+fn url_name(url: &str) -> String {
+    let parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            debug!("Failed to parse URL {}: {}", url, error);
+            let path = url.split(|c| c == '?' || c == '#').next().unwrap_or(url);
+            return match path.rsplit('/').find(|segment| !segment.is_empty()) {
+                Some(segment) => percent_decode(segment),
+                None => {
+                    debug!("Using whole URL {} as name", url);
+                    url.to_string()
+                }
+            };
+        }
+    };
+    if let Some(segment) = parsed
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+    {
+        percent_decode(segment)
+    } else if let Some(host) = parsed.host_str().filter(|host| !host.is_empty()) {
+        debug!("Using scheme and authority of URL {} as name", url);
+        format!("{}://{}", parsed.scheme(), host)
+    } else {
+        debug!("Using whole URL {} as name", url);
+        url.to_string()
+    }
+}
+
+/// The extension of files for multi-root workspaces.
+const CODE_WORKSPACE_EXTENSION: &str = ".code-workspace";
+
+/// Normalize `url` for workspace IDs.
+///
+/// Let the URL parser normalize scheme, host and percent-encoding, and remove a
+/// trailing slash from the path.  Leave URLs which don't parse alone.
+fn normalize_url(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(parsed) => {
+            let normalized = parsed.to_string();
+            match normalized.strip_suffix('/') {
+                Some(stripped) if parsed.path() != "/" => stripped.to_string(),
+                _ => normalized,
+            }
+        }
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Get the ID of the workspace at `url` for the app whose provider is at `objpath`.
+///
+/// The ID is a hash of `objpath` and the normalized `url`, so it remains stable across
+/// restarts and reparses of the storage, and doesn't depend on the desktop ID of the app.
+pub fn workspace_id(objpath: &str, url: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(objpath.as_bytes());
+    hasher.update(b"\0");
+    hasher.update(normalize_url(url).as_bytes());
+    format!("vscode-search-provider-{}", hex::encode(hasher.finalize()))
+}
+
+/// Add a workspace of the app with `app_id` at the given `url` to `workspaces`.
+///
+/// `objpath` is the object path of the provider for the app; see [`workspace_id`].
+///
+/// Take name and description of the workspace from the path of `file://` URLs,
+/// and fall back to the last segment and the whole URL for other URLs.  Name
+/// multi-root workspaces after their `.code-workspace` file, without extension.
+///
+/// If the user gave the workspace a `label` use it as name instead.
+///
+/// Skip the workspace if `workspaces` already has a workspace with an equivalent URL,
+/// e.g. with a trailing slash, to keep the first, most recent entry and its label.
+pub fn add_workspace(
+    workspaces: &mut IndexMap<String, RecentWorkspace>,
+    objpath: &str,
+    app_id: &str,
+    url: String,
+    label: Option<String>,
+) {
+    let id = workspace_id(objpath, &url);
+    if workspaces.contains_key(&id) {
+        trace!("Skipping duplicate workspace {}", url);
+        return;
+    }
+    let (mut name, description) = file_url_name_and_path(&url)
+        .or_else(|| remote_name_and_description(&url))
+        .or_else(|| github_name_and_description(&url))
+        .unwrap_or_else(|| (url_name(&url), percent_decode(&url)));
+    if let Some(stem) = name
+        .strip_suffix(CODE_WORKSPACE_EXTENSION)
+        .filter(|stem| !stem.is_empty())
+    {
+        name = stem.to_string();
+    }
+    if let Some(label) = label.filter(|label| !label.is_empty()) {
+        name = label;
+    }
+    let mut workspace = RecentWorkspace {
+        name,
+        path: file_url_path(&url),
+        host: remote_host(&url),
+        project: None,
+        language: None,
+        url,
+        description,
+        app_id: app_id.to_string(),
+        is_file: false,
+        keys: SearchKeys::default(),
+    };
+    workspace.keys = SearchKeys::new(&workspace);
+    trace!("Found recent workspace {:?}", workspace);
+    workspaces.insert(id, workspace);
+}
+
+/// Add a recently opened file of the app with `app_id` at the given `url` to `workspaces`.
+///
+/// Name and describe the file like a workspace, and skip duplicates likewise; see
+/// [`add_workspace`].
+pub fn add_recent_file(
+    workspaces: &mut IndexMap<String, RecentWorkspace>,
+    objpath: &str,
+    app_id: &str,
+    url: String,
+) {
+    let id = workspace_id(objpath, &url);
+    if workspaces.contains_key(&id) {
+        trace!("Skipping duplicate file {}", url);
+        return;
+    }
+    let (name, description) =
+        file_url_name_and_path(&url).unwrap_or_else(|| (url_name(&url), percent_decode(&url)));
+    let mut file = RecentWorkspace {
+        name,
+        path: file_url_path(&url),
+        host: remote_host(&url),
+        project: None,
+        language: None,
+        url,
+        description,
+        app_id: app_id.to_string(),
+        is_file: true,
+        keys: SearchKeys::default(),
+    };
+    file.keys = SearchKeys::new(&file);
+    trace!("Found recent file {:?}", file);
+    workspaces.insert(id, file);
+}
+
+/// Remove all local workspaces whose path no longer exists from `workspaces`.
+fn remove_missing_workspaces(workspaces: &mut IndexMap<String, RecentWorkspace>) {
+    workspaces.retain(|_, workspace| match &workspace.path {
+        Some(path) if !path.exists() => {
+            debug!("Hiding missing workspace {}", path.display());
+            false
+        }
+        _ => true,
+    });
+}
+
+/// Remove all workspaces matching the `exclude` patterns from `workspaces`.
+///
+/// Match the path of local workspaces, and the decoded URL of all other workspaces.
+fn remove_excluded_workspaces(
+    workspaces: &mut IndexMap<String, RecentWorkspace>,
+    exclude: &GlobSet,
+) {
+    if exclude.is_empty() {
+        return;
+    }
+    workspaces.retain(|_, workspace| {
+        let excluded = match &workspace.path {
+            Some(path) => exclude.is_match(path),
+            None => exclude.is_match(percent_decode(&workspace.url)),
+        };
+        if excluded {
+            debug!("Hiding excluded workspace {}", workspace.url);
+        }
+        !excluded
+    });
+}
+
+/// Add the names and languages of projects in local workspaces to `workspaces`.
+///
+/// Read name and language from the manifest of the project, e.g. `Cargo.toml` or
+/// `package.json`; see [`read_project`].  Skip recent files and workspaces which
+/// already have a project.
+fn add_projects(workspaces: &mut IndexMap<String, RecentWorkspace>) {
+    for workspace in workspaces.values_mut() {
+        if workspace.is_file || workspace.language.is_some() {
+            continue;
+        }
+        if let Some(project) = workspace.path.as_deref().and_then(read_project) {
+            workspace.project = project.name;
+            workspace.language = Some(project.language);
+            workspace.keys = SearchKeys::new(workspace);
+        }
+    }
+}
+
+/// Skip workspaces with the same URL as a preceding workspace in `workspaces`.
+///
+/// The combined provider finds the same folder in every app which opened it;
+/// keep only the first of these workspaces, to open the folder in the first app.
+/// Compare normalized URLs, so that trailing-slash variants count as the same.
+fn unique_workspaces(
+    workspaces: &IndexMap<String, RecentWorkspace>,
+) -> impl Iterator<Item = (&String, &RecentWorkspace)> {
+    let mut urls = HashSet::new();
+    workspaces
+        .iter()
+        .filter(move |(_, workspace)| urls.insert(normalize_url(&workspace.url)))
+}
+
+/// Score a match in `url` which ends at the byte index `end` by its path segment.
+///
+/// A match in the last segment scores 1, a match in its parent ½, in the grandparent
+/// ⅓, and so on.  Ignore trailing slashes, so that `/foo/mdcat/` has the same last
+/// segment as `/foo/mdcat`.
+fn segment_score(url: &str, end: usize) -> f64 {
+    let depth = url[end..].trim_end_matches('/').matches('/').count();
+    1.0 / (depth + 1) as f64
+}
+
+/// Match all `parts` in the given order within `url`.
+///
+/// Return the end of the match of the last part.
+fn match_in_order(url: &str, parts: &[&str]) -> Option<usize> {
+    parts.iter().try_fold(0, |start, part| {
+        url[start..]
+            .find(part)
+            .map(|index| start + index + part.len())
+    })
+}
+
+/// Calculate how well `workspace` matches all of the given `terms`, including name bonus.
+///
+/// Return a score of 0 if any term doesn't match at all.  See [`terms_score`] for scoring.
+#[cfg(test)]
+fn match_score<S: AsRef<str>>(workspace: &RecentWorkspace, terms: &[S]) -> f64 {
+    let terms = fold_terms(terms);
+    add_name_bonus(
+        workspace,
+        &terms,
+        terms_score(workspace, 0.0, &terms).unwrap_or(0.0),
+    )
+}
+
+/// The maximum number of characters of a term to match.
+///
+/// GNOME Shell passes whatever the user typed or pasted; no workspace URL is this long,
+/// and capping terms bounds the time of matching a term against a large history.
+const MAX_TERM_LENGTH: usize = 256;
+
+/// Fold the case of `c` and strip its accents, and push the result to `folded`.
+///
+/// Decompose `c` into its compatibility decomposition (NFKD), drop all combining marks,
+/// and fold the case of the remaining characters, e.g. `Ü` becomes `u`, `ß` becomes
+/// `ss`, and `ﬁ` becomes `fi`.
+fn push_folded(c: char, folded: &mut String) {
+    decompose_compatible(c, |c| {
+        if !is_combining_mark(c) {
+            folded.extend(std::iter::once(c).default_case_fold());
+        }
+    });
+}
+
+/// Fold the case of `text` and strip its accents; see [`push_folded`].
+fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars() {
+        push_folded(c, &mut folded);
+    }
+    folded
+}
+
+/// Join quoted phrases in `terms` into single terms.
+///
+/// GNOME Shell splits the search at whitespace, so the phrase `"my project"` arrives as
+/// the terms `"my` and `project"`; join these with single spaces and without quotes, so
+/// that the whole phrase must match contiguously.  A phrase without closing quote
+/// extends to the last term, and a `-` or `~` before the opening quote applies to the
+/// whole phrase; see [`negated_term`] and [`optional_term`].  Drop empty phrases.
+fn join_phrases<S: AsRef<str>>(terms: &[S]) -> Vec<String> {
+    let mut joined = Vec::new();
+    // The prefix and the words of the current phrase
+    let mut phrase: Option<(&str, Vec<&str>)> = None;
+    let mut push_phrase = |prefix: &str, words: Vec<&str>| {
+        let words = words
+            .into_iter()
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>();
+        if !words.is_empty() {
+            joined.push(format!("{}{}", prefix, words.join(" ")));
+        }
+    };
+    for term in terms.iter().map(AsRef::as_ref) {
+        if let Some((prefix, mut words)) = phrase.take() {
+            match term.strip_suffix('"') {
+                Some(word) => {
+                    words.push(word);
+                    push_phrase(prefix, words);
+                }
+                None => {
+                    words.push(term);
+                    phrase = Some((prefix, words));
+                }
+            }
+            continue;
+        }
+        let start = usize::from(term.starts_with(|c| c == '-' || c == '~'));
+        match term[start..].strip_prefix('"') {
+            Some(rest) => match rest.strip_suffix('"') {
+                Some(word) => push_phrase(&term[..start], vec![word]),
+                None => phrase = Some((&term[..start], vec![rest])),
+            },
+            None => push_phrase("", vec![term]),
+        }
+    }
+    if let Some((prefix, words)) = phrase {
+        push_phrase(prefix, words);
+    }
+    joined
+}
+
+/// Fold all `terms` for matching; see [`push_folded`].
+///
+/// Join quoted phrases first; see [`join_phrases`].  Only take the first
+/// [`MAX_TERM_LENGTH`] characters of every term.
+fn fold_terms<S: AsRef<str>>(terms: &[S]) -> Vec<String> {
+    join_phrases(terms)
+        .iter()
+        .map(|term| fold(&term.chars().take(MAX_TERM_LENGTH).collect::<String>()))
+        .collect()
+}
+
+/// Whether a word starts at `c`, given the characters `before` and `after` `c`.
+fn is_word_start(before: Option<char>, c: char, after: Option<char>) -> bool {
+    c.is_alphanumeric()
+        && match before {
+            None => true,
+            Some(before) => {
+                !before.is_alphanumeric()
+                    || (before.is_lowercase() && c.is_uppercase())
+                    || (before.is_uppercase()
+                        && c.is_uppercase()
+                        && after.map_or(false, char::is_lowercase))
+            }
+        }
+}
+
+/// Fold `text` for matching, and find the start of all words in `text`.
+///
+/// Words start after `-`, `_`, `.`, `/` and any other character which is neither a
+/// letter nor a digit, and at CamelCase humps, e.g. `GnomeSearchProvider` has three
+/// words, and `HTTPServer` has two.
+///
+/// Return the folded text and the byte indices of the start of all words in the
+/// folded text; see [`push_folded`] for folding.
+fn fold_words(text: &str) -> (String, Vec<usize>) {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut folded = String::with_capacity(text.len());
+    let mut starts = Vec::new();
+    for (index, c) in chars.iter().enumerate() {
+        let before = index.checked_sub(1).map(|index| chars[index]);
+        if is_word_start(before, *c, chars.get(index + 1).copied()) {
+            starts.push(folded.len());
+        }
+        push_folded(*c, &mut folded);
+    }
+    (folded, starts)
+}
+
+/// Find the last match of the folded `term` at the start of a word of `text`.
+///
+/// `text` and `starts` are the folded text and its words, see [`fold_words`].
+///
+/// Return the byte index of the match in `text`.
+fn rfind_at_word_start(text: &str, starts: &[usize], term: &str) -> Option<usize> {
+    starts
+        .iter()
+        .rev()
+        .copied()
+        .find(|start| text[*start..].starts_with(term))
+}
+
+/// The initials of the words of the folded `name`.
+///
+/// `starts` are the starts of the words of `name`; see [`fold_words`].
+fn initials(name: &str, starts: &[usize]) -> String {
+    starts
+        .iter()
+        .filter_map(|start| name[*start..].chars().next())
+        .collect()
+}
+
+/// Whether the folded `term` abbreviates a name with the given `initials`.
+///
+/// `term` abbreviates a name if it has at least two characters and the initials
+/// of the leading words of the name spell `term`, e.g. `gsp` or `gs` for
+/// `gnome-search-provider` or `GnomeSearchProvider`; see [`initials`].  Such a match
+/// scores less than a name match but more than a URL match; see [`terms_score`].
+fn matches_acronym(initials: &str, term: &str) -> bool {
+    1 < term.chars().count() && initials.starts_with(term)
+}
+
+/// The minimum number of characters of a term to tolerate typos in.
+const MIN_TYPO_TERM_LENGTH: usize = 4;
+
+/// The maximum number of typos to tolerate in `term`.
+///
+/// Tolerate one typo in terms of at least [`MIN_TYPO_TERM_LENGTH`] characters, and
+/// two typos in terms twice as long.
+fn max_typos(term: &str) -> usize {
+    match term.chars().count() {
+        n if n < MIN_TYPO_TERM_LENGTH => 0,
+        n if n < 2 * MIN_TYPO_TERM_LENGTH => 1,
+        _ => 2,
+    }
+}
+
+/// The words of `name`, i.e. all runs of letters and digits.
+fn name_words(name: &str) -> impl Iterator<Item = &str> {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+}
+
+/// The edit distance between `a` and `b`.
+///
+/// Count insertions, deletions and substitutions of single characters, and
+/// transpositions of adjacent characters (optimal string alignment distance).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    // The distances of the last two rows, and the current row
+    let mut before_last = vec![0; b.len() + 1];
+    let mut last = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i;
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (last[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(last[j - 1] + cost);
+            if 1 < i && 1 < j && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before_last[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut before_last, &mut last);
+        std::mem::swap(&mut last, &mut current);
+    }
+    last[b.len()]
+}
+
+/// Whether the folded `term` matches the folded `name` or a word of `name` with a few typos.
+///
+/// Only tolerate typos in terms of at least [`MIN_TYPO_TERM_LENGTH`] characters, e.g.
+/// `mdact` for `mdcat`; see [`max_typos`] for how many.  [`terms_score`] only tries
+/// this if the term matches neither name nor URL, and scores such a match half of a
+/// name match.
+fn matches_with_typos(name: &str, term: &str) -> bool {
+    let max = max_typos(term);
+    let length = term.chars().count();
+    0 < max
+        && std::iter::once(name)
+            .chain(name_words(name))
+            .filter(|word| {
+                let word_length = word.chars().count();
+                word_length.max(length) - word_length.min(length) <= max
+            })
+            .any(|word| edit_distance(word, term) <= max)
+}
+
+/// The folded and decoded texts of a workspace to match search terms against.
+///
+/// Compute these once per workspace, instead of folding and decoding name and URL of
+/// every workspace for every term of every search.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SearchKeys {
+    /// The folded name; see [`fold_words`].
+    name: String,
+    /// The starts of the words of `name`.
+    name_starts: Vec<usize>,
+    /// The initials of the words of `name`; see [`initials`].
+    initials: String,
+    /// The name transliterated to ASCII and folded, or empty if the name is ASCII.
+    transliterated: String,
+    /// The decoded and folded URL.
+    url: String,
+    /// The starts of the words of `url`.
+    url_starts: Vec<usize>,
+    /// The folded project name, or empty if the workspace has no project.
+    project: String,
+    /// The folded remote location, or empty if the workspace isn't remote.
+    host: String,
+}
+
+impl SearchKeys {
+    /// Compute the search keys of `workspace`.
+    fn new(workspace: &RecentWorkspace) -> Self {
+        let (name, name_starts) = fold_words(&workspace.name);
+        let (url, url_starts) = fold_words(&percent_decode(&workspace.url));
+        let transliterated = if workspace.name.is_ascii() {
+            String::new()
+        } else {
+            fold(&deunicode(&workspace.name))
+        };
+        Self {
+            initials: initials(&name, &name_starts),
+            name,
+            name_starts,
+            transliterated,
+            url,
+            url_starts,
+            project: workspace.project.as_deref().map(fold).unwrap_or_default(),
+            host: workspace.host.as_deref().map(fold).unwrap_or_default(),
+        }
+    }
+}
+
+/// Add the scores of all folded `terms` for `workspace` to `score`.
+///
+/// A term scores highest in the name of the workspace, particularly at the start of a
+/// word, and less in the transliterated name, the project name, as an acronym of the
+/// name, or with typos in the name; see [`fold_words`], [`add_projects`],
+/// [`matches_acronym`] and [`matches_with_typos`].  A term in the URL or the remote
+/// location scores least, by the segment it matches in; see [`segment_score`].  A
+/// term with path separators, e.g. `dev/mdcat`, matches its segments in order.
+///
+/// Exclude workspaces by negated terms, and score optional terms without requiring
+/// them; see [`negated_term`] and [`optional_term`].  Don't add the bonus for exact and
+/// prefix name matches; see [`add_name_bonus`].
+///
+/// Match against the folded and decoded [`SearchKeys`] of the workspace, so that e.g.
+/// `uber` matches `Über-App`.  Return `None` if any term doesn't match, or any negated
+/// term matches.
+fn terms_score(workspace: &RecentWorkspace, score: f64, terms: &[String]) -> Option<f64> {
+    let SearchKeys {
+        name,
+        name_starts,
+        initials,
+        transliterated,
+        url,
+        url_starts,
+        project,
+        host,
+    } = &workspace.keys;
+    // Score a URL match by its segment, and score matches at word starts higher.
+    let url_score = |index: usize, term: &str, word_start: bool| {
+        let segment = segment_score(url, index + term.len());
+        if word_start {
+            0.5 + segment / 2.0
+        } else {
+            segment / 2.0
+        }
+    };
+    // The score of a single `term`, or `None` if `term` doesn't match
+    let term_score = |term: &str| {
+        let parts = term
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>();
+        if rfind_at_word_start(name, name_starts, term).is_some() {
+            Some(12.0)
+        } else if name.contains(term) {
+            Some(10.0)
+        } else if transliterated.contains(term) {
+            Some(9.0)
+        } else if project.contains(term) {
+            Some(8.0)
+        } else if 1 < parts.len() {
+            match_in_order(url, &parts).map(|end| 1.0 + segment_score(url, end))
+        } else if matches_acronym(initials, term) {
+            Some(7.0)
+        } else if host.contains(term) {
+            Some(1.0)
+        } else {
+            rfind_at_word_start(url, url_starts, term)
+                .map(|index| url_score(index, term, true))
+                .or_else(|| url.rfind(term).map(|index| url_score(index, term, false)))
+                .or_else(|| matches_with_typos(name, term).then(|| 5.0))
+        }
+    };
+    terms.iter().try_fold(score, |score, term| {
+        if let Some(excluded) = negated_term(term) {
+            [name, transliterated, url, project, host]
+                .iter()
+                .all(|text| !text.contains(excluded))
+                .then(|| score)
+        } else if let Some(optional) = optional_term(term) {
+            Some(score + term_score(optional).unwrap_or(0.0))
+        } else {
+            term_score(term).map(|term_score| score + term_score)
+        }
+    })
+}
+
+/// Get the excluded text of `term` if `term` is negated.
+///
+/// A term is negated if it starts with `-` and has more characters after the `-`;
+/// a single `-` isn't negated.  A negated term, e.g. `-legacy`, excludes workspaces
+/// whose name, URL, project name or remote location contains the excluded text.
+fn negated_term(term: &str) -> Option<&str> {
+    term.strip_prefix('-')
+        .filter(|excluded| !excluded.is_empty())
+}
+
+/// Get the text of `term` if `term` is optional.
+///
+/// A term is optional if it starts with `~` and has more characters after the `~`;
+/// a single `~` isn't optional.  An optional term, e.g. `~api`, adds to the score if
+/// it matches, but doesn't exclude workspaces otherwise, so that workspaces match any
+/// of the optional terms.
+fn optional_term(term: &str) -> Option<&str> {
+    term.strip_prefix('~').filter(|text| !text.is_empty())
+}
+
+/// A filter term, which restricts the workspaces to search in.
+///
+/// A filter term has the form `key:value`; see [`Filter::parse`] for all filters.
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    /// Only remote workspaces whose authority or location contains the text.
+    Remote(String),
+    /// Only recently opened files.
+    File,
+    /// Only local workspaces with a project in the language.
+    Lang(String),
+}
+
+impl Filter {
+    /// Parse a folded `term` as filter.
+    ///
+    /// `remote:` restricts the search to remote workspaces, and `remote:<text>` to remote
+    /// workspaces whose kind or location contains `text`, e.g. `remote:ssh` or
+    /// `remote:buildserver`; see [`remote_location`].  `file:` restricts the search to
+    /// recently opened files, and `lang:<language>` to workspaces with a project in the
+    /// given language, e.g. `lang:rust`; see [`add_projects`].
+    ///
+    /// Return `None` if `term` isn't a filter.  `file:` with a value isn't a filter, so
+    /// that terms like `file:///home` still match URLs.
+    fn parse(term: &str) -> Option<Self> {
+        let (key, value) = term.split_once(':')?;
+        match key {
+            "remote" => Some(Self::Remote(value.to_string())),
+            "file" if value.is_empty() => Some(Self::File),
+            "lang" => Some(Self::Lang(canonical_language(value).to_string())),
+            _ => None,
+        }
+    }
+
+    /// Whether `workspace` passes this filter.
+    fn matches(&self, workspace: &RecentWorkspace) -> bool {
+        match self {
+            Self::Remote(text) => {
+                let keys = &workspace.keys;
+                let authority = keys
+                    .url
+                    .strip_prefix(REMOTE_SCHEME)
+                    .and_then(|rest| rest.strip_prefix("://"))
+                    .and_then(|rest| rest.split('/').next());
+                authority.map_or(false, |authority| {
+                    authority.contains(text.as_str()) || keys.host.contains(text.as_str())
+                })
+            }
+            Self::File => workspace.is_file,
+            Self::Lang(language) => workspace
+                .language
+                .map_or(false, |lang| language.is_empty() || lang == language),
+        }
+    }
+}
+
+/// Split folded `terms` into filters and the terms to score.
+///
+/// See [`Filter::parse`].
+fn parse_filters(terms: Vec<String>) -> (Vec<Filter>, Vec<String>) {
+    let mut filters = Vec::new();
+    let terms = terms
+        .into_iter()
+        .filter(|term| match Filter::parse(term) {
+            Some(filter) => {
+                filters.push(filter);
+                false
+            }
+            None => true,
+        })
+        .collect();
+    (filters, terms)
+}
+
+/// Whether a search for `filters` and `terms` matches workspaces without scoring.
+///
+/// A search with only filters doesn't have any terms to score, and a search for a
+/// single character matches almost every workspace anyway, when the user just started
+/// typing; both just show all workspaces which pass the filters, most recent first.
+fn is_unscored_search(filters: &[Filter], terms: &[String]) -> bool {
+    match terms {
+        [] => !filters.is_empty(),
+        [term] => term.chars().count() == 1,
+        _ => false,
+    }
+}
+
+/// The bonus for terms which are exactly the name of a workspace.
+const EXACT_NAME_BONUS: f64 = 5.0;
+
+/// The bonus for terms which are a prefix of the name of a workspace.
+const NAME_PREFIX_BONUS: f64 = 3.0;
+
+/// Add the bonus for an exact or prefix name match of folded `terms` to the `score` of `workspace`.
+///
+/// Add [`EXACT_NAME_BONUS`] if the terms joined with spaces are exactly the name, and
+/// [`NAME_PREFIX_BONUS`] if the name only starts with the joined terms.  Ignore negated
+/// terms, and the `~` of optional terms; see [`negated_term`] and [`optional_term`].
+fn add_name_bonus(workspace: &RecentWorkspace, terms: &[String], score: f64) -> f64 {
+    let name = &workspace.keys.name;
+    let joined = terms
+        .iter()
+        .filter(|term| negated_term(term).is_none())
+        .map(|term| optional_term(term).unwrap_or(term))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if score <= 0.0 || joined.is_empty() {
+        score
+    } else if &joined == name {
+        score + EXACT_NAME_BONUS
+    } else if name.starts_with(&joined) {
+        score + NAME_PREFIX_BONUS
+    } else {
+        score
+    }
+}
+
+/// Scores of the last search, to score subsearches incrementally.
+///
+/// A subsearch usually only adds terms to the terms of the previous search; in this case
+/// only score the new terms, and add their scores to the cached scores of the previous terms.
+///
+/// Clear the cache before every new search, because it only has scores of previous results.
+#[derive(Debug, Default)]
+struct ScoreCache {
+    /// The filters of the last search.
+    filters: Vec<Filter>,
+    /// The folded terms of the last search, without filters.
+    terms: Vec<String>,
+    /// The scores of all workspaces matching `terms`, without exact match bonus.
+    ///
+    /// Also has workspaces without score, e.g. for only negated terms; more terms may
+    /// still make them match.
+    scores: HashMap<String, f64>,
+    /// Whether the last search left out workspaces in `scores`, i.e. workspaces without
+    /// score or with less than the minimum score.
+    dropped: bool,
+}
+
+impl ScoreCache {
+    /// Find all `workspaces` matching `terms`.
+    ///
+    /// Skip workspaces which don't pass all filters in `terms`; see [`Filter::parse`].
+    /// Score the remaining workspaces with [`terms_score`] and [`add_name_bonus`], and
+    /// discard workspaces which score less than `min_score`.  Then add the frecency bonus
+    /// of the remaining workspaces from `bonuses`, by URL, and return the IDs of all
+    /// matching workspaces, best match first; see [`rank_workspaces`].
+    ///
+    /// If `terms` only has filters or a single character return all remaining workspaces
+    /// instead, without `min_score` and `bonuses`, in the order of `workspaces`, i.e.
+    /// most recent first; see [`is_unscored_search`].
+    ///
+    /// Keep matching workspaces whose normalized URL is in `pinned` regardless of
+    /// `min_score`, and return them before all other workspaces.
+    ///
+    /// Reuse cached scores if the cached terms are a prefix of `terms` and the filters
+    /// didn't change, and cache the scores for `terms` afterwards, unless the search
+    /// didn't score workspaces.  Remember whether the returned IDs left out any cached
+    /// workspace; a subsearch then needs to look at all workspaces again.
+    fn find_matching_workspaces<'a, I, S>(
+        &mut self,
+        workspaces: I,
+        terms: &[S],
+        min_score: f64,
+        bonuses: &HashMap<String, f64>,
+        pinned: &HashSet<String>,
+    ) -> Vec<String>
+    where
+        I: IntoIterator<Item = (&'a String, &'a RecentWorkspace)>,
+        S: AsRef<str>,
+    {
+        let (filters, terms) = parse_filters(fold_terms(terms));
+        let unscored = is_unscored_search(&filters, &terms);
+        let incremental =
+            !self.terms.is_empty() && terms.starts_with(&self.terms) && filters == self.filters;
+        if incremental {
+            debug!(
+                "Scoring {:?} incrementally from {:?}",
+                &terms[self.terms.len()..],
+                self.terms
+            );
+        }
+        let mut scores = HashMap::new();
+        let mut pinned_ids = HashSet::new();
+        let matches_filters =
+            |workspace: &RecentWorkspace| filters.iter().all(|filter| filter.matches(workspace));
+        let mut ids = if unscored {
+            workspaces
+                .into_iter()
+                .filter(|(_, workspace)| matches_filters(workspace))
+                .map(|(id, workspace)| {
+                    if !pinned.is_empty() && pinned.contains(&normalize_url(&workspace.url)) {
+                        pinned_ids.insert(id.clone());
+                    }
+                    id.clone()
+                })
+                .collect()
+        } else {
+            rank_workspaces(workspaces, |id, workspace| {
+                if !matches_filters(workspace) {
+                    return 0.0;
+                }
+                let score = if incremental {
+                    self.scores.get(id).and_then(|score| {
+                        terms_score(workspace, *score, &terms[self.terms.len()..])
+                    })
+                } else {
+                    terms_score(workspace, 0.0, &terms)
+                };
+                if let Some(score) = score {
+                    // Cache weak matches and matches without score as well; more terms
+                    // may raise their score
+                    scores.insert(id.clone(), score);
+                }
+                match score {
+                    Some(score) if 0.0 < score => {
+                        let score = add_name_bonus(workspace, &terms, score);
+                        let is_pinned =
+                            !pinned.is_empty() && pinned.contains(&normalize_url(&workspace.url));
+                        if is_pinned {
+                            pinned_ids.insert(id.clone());
+                        }
+                        if is_pinned || min_score <= score {
+                            score + bonuses.get(&workspace.url).copied().unwrap_or(0.0)
+                        } else {
+                            0.0
+                        }
+                    }
+                    _ => 0.0,
+                }
+            })
+        };
+        // Stable, so pinned and other workspaces each keep their ranking
+        ids.sort_by_key(|id| !pinned_ids.contains(id));
+        self.filters = filters;
+        // Don't score the next search incrementally from a search without scores
+        self.terms = if unscored { Vec::new() } else { terms };
+        // Every returned workspace has a cached score, unless the search didn't score
+        self.dropped = ids.len() < scores.len();
+        self.scores = scores;
+        ids
+    }
+}
+
+/// Find all workspaces matching `terms`.
+///
+/// Return the IDs of all matching workspaces, best match first; see [`rank_workspaces`].
+#[cfg(test)]
+fn find_matching_workspaces<'a, I, S>(workspaces: I, terms: &[S]) -> Vec<String>
+where
+    I: IntoIterator<Item = (&'a String, &'a RecentWorkspace)>,
+    S: AsRef<str>,
+{
+    rank_workspaces(workspaces, |_, workspace| match_score(workspace, terms))
+}
+
+/// The depth of the path of `url`, i.e. the number of its non-empty path segments.
+///
+/// Count segments after the authority of `url`, or of all of `url` if it has no
+/// authority.
+fn path_depth(url: &str) -> usize {
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("", |index| &rest[index..]),
+        None => url,
+    };
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .count()
+}
+
+/// Rank all `workspaces` with a positive `score`.
+///
+/// `workspaces` must have the workspaces of each app in the order of their storage,
+/// i.e. most recent first.  Return the IDs of these workspaces ordered by
+///
+/// 1. score, best score first,
+/// 2. depth of the path, shallowest first, so that e.g. the root of a monorepo ranks
+///    above its packages; see [`path_depth`],
+/// 3. app, in order of the first appearance of each app in `workspaces`, to keep
+///    workspaces of the same app together among equal scores, and
+/// 4. the order of `workspaces`, so that recently opened workspaces rank above older
+///    workspaces which match equally well.
+///
+/// Ignore workspaces whose score is NaN.
+fn rank_workspaces<'a, I, F>(workspaces: I, mut score: F) -> Vec<String>
+where
+    I: IntoIterator<Item = (&'a String, &'a RecentWorkspace)>,
+    F: FnMut(&String, &RecentWorkspace) -> f64,
+{
+    let mut apps: Vec<&str> = Vec::new();
+    let mut matches: Vec<(f64, usize, usize, usize, &String)> = workspaces
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, (id, workspace))| {
+            let app = match apps.iter().position(|app| *app == workspace.app_id) {
+                Some(app) => app,
+                None => {
+                    apps.push(&workspace.app_id);
+                    apps.len() - 1
+                }
+            };
+            let score = score(id, workspace);
+            // Also false for NaN
+            if 0.0 < score {
+                Some((score, path_depth(&workspace.url), app, index, id))
+            } else {
+                None
+            }
+        })
+        .collect();
+    matches.sort_by(
+        |(score_a, depth_a, app_a, index_a, _), (score_b, depth_b, app_b, index_b, _)| {
+            score_b
+                .total_cmp(score_a)
+                .then(depth_a.cmp(depth_b))
+                .then(app_a.cmp(app_b))
+                .then(index_a.cmp(index_b))
+        },
+    );
+    matches
+        .into_iter()
+        .map(|(_, _, _, _, id)| id.clone())
+        .collect()
+}
+
+/// The icon of recently opened files.
+const RECENT_FILE_ICON: &str = "text-x-generic";
+
+/// The ID of the placeholder result for an empty list of recent workspaces.
+const PLACEHOLDER_ID: &str = "vscode-search-provider-no-recent-workspaces";
+
+/// Settings for search providers.
+#[derive(Debug, Clone, Default)]
+pub struct ProviderSettings {
+    /// Whether to return a placeholder result if there are no recent workspaces at all.
+    pub placeholder_when_empty: bool,
+    /// Whether to show recently opened files along with workspaces.
+    pub include_recent_files: bool,
+    /// Whether to hide local workspaces which no longer exist.
+    pub hide_missing_workspaces: bool,
+    /// Whether to match the project names in manifests of local workspaces.
+    pub match_project_names: bool,
+    /// Hide workspaces matching any of these patterns; see [`remove_excluded_workspaces`].
+    pub exclude: GlobSet,
+    /// The URLs of pinned workspaces, to rank above all other matching workspaces.
+    pub pinned: Vec<String>,
+    /// The template for descriptions of results, or `None` for the default description.
+    pub description: Option<DescriptionTemplate>,
+    /// The maximum size of storage to read, in bytes, or `None` to read storage of any size.
+    pub max_storage_size: Option<u64>,
+    /// The maximum number of results to return, or `None` to return all results.
+    pub max_results: Option<usize>,
+    /// Discard results which score less than this, before adding frecency bonuses.
+    pub min_score: f64,
+}
+
+/// A search provider for recent VSCode workspaces.
+///
+/// The provider exposes the recent workspaces of all its apps, and launches every
+/// workspace with the app it belongs to.  Usually a provider has just a single app;
+/// the combined provider has all installed apps.
+pub struct VscodeSearchProvider {
+    apps: Vec<ProviderApp>,
+    launcher: AppLaunchClient,
+    activity: ActivityTracker,
+    frecency: Frecency,
+    settings: ProviderSettings,
+    /// The normalized URLs of pinned workspaces.
+    pinned: HashSet<String>,
+    /// The home directory, to abbreviate in the paths of local workspaces.
+    home: PathBuf,
+    recent_workspaces: IndexMap<String, RecentWorkspace>,
+    /// The unique workspaces of `recent_workspaces` to search in, or `None` if invalidated.
+    ///
+    /// Searches and subsearches share this snapshot until an update changes
+    /// `recent_workspaces`; see [`Self::invalidate_snapshot`].
+    snapshot: Option<IndexMap<String, RecentWorkspace>>,
+    scores: ScoreCache,
+    /// Whether the last search dropped results beyond the maximum number of results.
+    truncated: bool,
+    /// The changes of all apps as of the last successful update of `recent_workspaces`.
+    seen_changes: Option<Vec<usize>>,
+    /// The stamps of the sources of every app, by desktop ID, as of the last successful read.
+    stamps: HashMap<String, Vec<(PathBuf, FileStamp)>>,
+}
+
+impl VscodeSearchProvider {
+    /// Create a new search provider for the given `apps`, launching workspaces with `launcher`.
+    ///
+    /// Record every call to the provider in `activity`, and every opened workspace in
+    /// `frecency`, to rank frequently and recently opened workspaces higher.
+    pub fn new(
+        apps: Vec<ProviderApp>,
+        launcher: AppLaunchClient,
+        activity: ActivityTracker,
+        frecency: Frecency,
+        settings: ProviderSettings,
+    ) -> Self {
+        Self {
+            apps,
+            launcher,
+            activity,
+            frecency,
+            pinned: settings
+                .pinned
+                .iter()
+                .map(|url| normalize_url(url))
+                .collect(),
+            home: glib::home_dir(),
+            settings,
+            recent_workspaces: IndexMap::new(),
+            snapshot: None,
+            scores: ScoreCache::default(),
+            truncated: false,
+            seen_changes: None,
+            stamps: HashMap::new(),
+        }
+    }
+
+    /// Get the current workspaces of the app with the given `desktop_id`.
+    fn cached_workspaces(&self, desktop_id: &str) -> Vec<(String, RecentWorkspace)> {
+        self.recent_workspaces
+            .iter()
+            .filter(|(_, workspace)| workspace.app_id == desktop_id)
+            .map(|(id, workspace)| (id.clone(), workspace.clone()))
+            .collect()
+    }
+
+    /// The current changes of all apps, or `None` if we don't watch some app.
+    fn storage_changes(&self) -> Option<Vec<usize>> {
+        self.apps
+            .iter()
+            .map(|app| app.changes.as_ref().map(StorageChanges::count))
+            .collect()
+    }
+
+    /// The apps of this provider.
+    pub fn apps(&self) -> &[ProviderApp] {
+        &self.apps
+    }
+
+    /// Get the app with the given `desktop_id`.
+    fn app(&self, desktop_id: &str) -> Option<&ProviderApp> {
+        self.apps.iter().find(|app| app.desktop_id == desktop_id)
+    }
+
+    /// Describe `workspace` in search results.
+    ///
+    /// Render the description template of the settings, if any.  Otherwise describe
+    /// local workspaces with their path, and prefix recently opened files with `File`,
+    /// and, if we've got more than one app, the label of the app, to tell apart
+    /// workspaces of different apps.  Abbreviate the home directory in local paths
+    /// either way.
+    fn describe(&self, workspace: &RecentWorkspace) -> String {
+        // Local workspaces have their decoded path as description
+        let local_path = workspace
+            .path
+            .as_ref()
+            .map(|_| abbreviate_home(&workspace.description, &self.home));
+        match &self.settings.description {
+            Some(template) => template.render(|placeholder| match placeholder {
+                Placeholder::Path => local_path
+                    .clone()
+                    .unwrap_or_else(|| url_path(&workspace.url)),
+                Placeholder::Host => workspace.host.clone().unwrap_or_default(),
+                Placeholder::Kind => location_kind(&workspace.url),
+                Placeholder::App => self
+                    .app(&workspace.app_id)
+                    .map(|app| app.label.clone())
+                    .unwrap_or_default(),
+                Placeholder::LastOpened => self
+                    .frecency
+                    .time_since_visit(&workspace.url)
+                    .map(format_age)
+                    .unwrap_or_default(),
+            }),
+            None => {
+                let location = local_path.unwrap_or_else(|| workspace.description.clone());
+                let description = if workspace.is_file {
+                    format!("File {}", location)
+                } else {
+                    location
+                };
+                match self.app(&workspace.app_id) {
+                    Some(app) if 1 < self.apps.len() => format!("{}: {}", app.label, description),
+                    _ => description,
+                }
+            }
+        }
+    }
+
+    /// Keep only the best `ids` up to the maximum number of results.
+    ///
+    /// Remember whether this dropped any `ids`; see [`Self::search`].
+    fn limit_results(&mut self, mut ids: Vec<String>) -> Vec<String> {
+        self.truncated = false;
+        if let Some(max) = self.settings.max_results {
+            self.truncated = max < ids.len();
+            ids.truncate(max);
+        }
+        ids
+    }
+
+    /// Invalidate the snapshot of workspaces to search in.
+    ///
+    /// Call this whenever `recent_workspaces` changes, to take a new snapshot on the
+    /// next search.
+    fn invalidate_snapshot(&mut self) {
+        debug!("Invalidating snapshot of recent workspaces");
+        self.snapshot = None;
+    }
+
+    /// Find all workspaces matching `terms` in the current snapshot.
+    ///
+    /// Take a new snapshot of the unique recent workspaces if there's none, and reuse
+    /// the scores of the previous search where possible; see [`ScoreCache`].
+    ///
+    /// With `previous_results` only search among these workspaces, unless the previous
+    /// search left out workspaces which can still match the refined `terms`: results
+    /// beyond the maximum number of results, e.g. after a search for a single character,
+    /// and weak matches below the minimum score or matches without any score, e.g. for
+    /// only negated terms; see [`ScoreCache`].
+    fn search(&mut self, terms: &[String], previous_results: Option<&[String]>) -> Vec<String> {
+        let snapshot = self.snapshot.get_or_insert_with(|| {
+            unique_workspaces(&self.recent_workspaces)
+                .map(|(id, workspace)| (id.clone(), workspace.clone()))
+                .collect()
+        });
+        let bonuses = self.frecency.bonuses(terms);
+        let ids = match previous_results {
+            Some(previous_results) if !self.truncated && !self.scores.dropped => {
+                // Keep the order of the snapshot, to rank recent workspaces first
+                let previous_results = previous_results.iter().collect::<HashSet<_>>();
+                self.scores.find_matching_workspaces(
+                    snapshot
+                        .iter()
+                        .filter(|(id, _)| previous_results.contains(id)),
+                    terms,
+                    self.settings.min_score,
+                    &bonuses,
+                    &self.pinned,
+                )
+            }
+            _ => self.scores.find_matching_workspaces(
+                snapshot.iter(),
+                terms,
+                self.settings.min_score,
+                &bonuses,
+                &self.pinned,
+            ),
+        };
+        debug!("Found ids {:?}", ids);
+        self.limit_results(ids)
+    }
+
+    /// Whether to show the placeholder result instead of workspaces.
+    fn show_placeholder(&self) -> bool {
+        self.settings.placeholder_when_empty && self.recent_workspaces.is_empty()
+    }
+
+    /// Get metadata for the placeholder result.
+    fn placeholder_meta(&self) -> HashMap<String, Value<'static>> {
+        let mut meta = HashMap::new();
+        meta.insert(
+            "name".to_string(),
+            Value::from("No recent VS Code workspaces"),
+        );
+        if let Some(app) = self.apps.first() {
+            if let Some(icon) = app.icon.as_ref() {
+                meta.insert("gicon".to_string(), Value::from(icon.clone()));
+            }
+            meta.insert(
+                "description".to_string(),
+                Value::from(format!("Open {}", app.label)),
+            );
+        }
+        meta.insert("id".to_string(), Value::from(PLACEHOLDER_ID));
+        meta
+    }
+
+    /// Launch the first app of this provider without any workspace.
+    async fn launch_first_app(&self) -> zbus::fdo::Result<()> {
+        match self.apps.first() {
+            Some(app) => {
+                info!("Launching app {}", app.desktop_id);
+                self.launcher
+                    .launch_app(app.desktop_id.as_str().into())
+                    .await
+                    .map_err(|error| {
+                        error!("Failed to launch app {}: {}", app.desktop_id, error);
+                        zbus::fdo::Error::SpawnFailed(format!(
+                            "Failed to launch app {}: {}",
+                            app.desktop_id, error
+                        ))
+                    })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Launch a remote `workspace` with the command of its app and the given CLI `option`.
+    fn launch_remote(&self, workspace: &RecentWorkspace, option: &str) -> zbus::fdo::Result<()> {
+        let app = self.app(&workspace.app_id).ok_or_else(|| {
+            zbus::fdo::Error::Failed(format!("App {} not found", workspace.app_id))
+        })?;
+        let command = app
+            .command
+            .iter()
+            .map(OsString::as_os_str)
+            .chain([OsStr::new(option), OsStr::new(&workspace.url)])
+            .collect::<Vec<_>>();
+        info!("Launching {:?}", command);
+        gio::Subprocess::newv(&command, gio::SubprocessFlags::NONE)
+            .map(|_| ())
+            .map_err(|error| {
+                error!(
+                    "Failed to launch {} with {:?}: {}",
+                    workspace.url, app.command, error
+                );
+                zbus::fdo::Error::SpawnFailed(format!(
+                    "Failed to launch {} with {:?}: {}",
+                    workspace.url, app.command, error
+                ))
+            })
+    }
+
+    /// Launch `workspace` with the app it belongs to.
+    ///
+    /// Open remote workspaces through the CLI of the app, if the app has no server.
+    async fn launch_workspace(&self, workspace: &RecentWorkspace) -> zbus::fdo::Result<()> {
+        let server_url = self
+            .app(&workspace.app_id)
+            .and_then(|app| app.server_url.as_deref());
+        let url = match server_url {
+            Some(server_url) => server_workspace_url(server_url, &workspace.url, workspace.is_file)
+                .ok_or_else(|| {
+                    error!("Cannot open {} at {}", workspace.url, server_url);
+                    zbus::fdo::Error::Failed(format!(
+                        "Cannot open {} at {}",
+                        workspace.url, server_url
+                    ))
+                })?,
+            None => {
+                if let Some(option) = remote_cli_option(&workspace.url, workspace.is_file) {
+                    return self.launch_remote(workspace, option);
+                }
+                workspace.url.clone()
+            }
+        };
+        info!("Launching {} with {}", url, workspace.app_id);
+        self.launcher
+            .launch_uri(workspace.app_id.as_str().into(), url.clone())
+            .await
+            .map_err(|error| {
+                error!(
+                    "Failed to launch {} with {}: {}",
+                    url, workspace.app_id, error
+                );
+                zbus::fdo::Error::SpawnFailed(format!(
+                    "Failed to launch {} with {}: {}",
+                    url, workspace.app_id, error
+                ))
+            })
+    }
+
+    /// Update the recent workspaces of all apps of this provider.
+    ///
+    /// Skip apps whose recent workspaces can't be read, unless none of the apps
+    /// could be read at all.  If we fail to read the recent workspaces of an app,
+    /// e.g. because we may not read them or VSCode was just writing them, keep its
+    /// previously cached workspaces, if any.
+    ///
+    /// Keep the current workspaces if the sources of no app changed since the last
+    /// update; then only remove workspaces which no longer exist, if requested.
+    /// Likewise keep the current workspaces of every app whose sources still have
+    /// the same modification time and size, instead of parsing them again.
+    #[instrument(skip(self))]
+    async fn update_recent_workspaces(&mut self) -> Result<()> {
+        let changes = self.storage_changes();
+        if changes.is_some() && changes == self.seen_changes {
+            debug!("Recent workspaces unchanged, using cached workspaces");
+            if self.settings.hide_missing_workspaces {
+                let count = self.recent_workspaces.len();
+                remove_missing_workspaces(&mut self.recent_workspaces);
+                if self.recent_workspaces.len() != count {
+                    self.invalidate_snapshot();
+                }
+            }
+            return Ok(());
+        }
+        let mut workspaces = IndexMap::new();
+        let mut stamps = HashMap::new();
+        let mut last_error = None;
+        let mut read_any = false;
+        let mut read_all = true;
+        for app in &self.apps {
+            let app_stamps = app
+                .config_dirs
+                .iter()
+                .flat_map(|dir| WorkspaceSource::stamps(dir))
+                .collect::<Vec<_>>();
+            if self.stamps.get(&app.desktop_id) == Some(&app_stamps) {
+                debug!(
+                    "Sources of {} unchanged, keeping cached workspaces",
+                    app.desktop_id
+                );
+                read_any = true;
+                workspaces.extend(self.cached_workspaces(&app.desktop_id));
+                stamps.insert(app.desktop_id.clone(), app_stamps);
+                continue;
+            }
+            info!("Finding recent workspaces for {}", app.desktop_id);
+            match read_app_sources(app, self.settings.max_storage_size).await {
+                Ok(sources) => {
+                    read_any = true;
+                    stamps.insert(app.desktop_id.clone(), app_stamps);
+                    for entry in WorkspaceSource::merge_recent_entries(sources) {
+                        match entry.url {
+                            RecentUrl::Workspace(url) => {
+                                trace!("Discovered workspace url {}", url);
+                                add_workspace(
+                                    &mut workspaces,
+                                    &app.objpath,
+                                    &app.desktop_id,
+                                    url,
+                                    entry.label,
+                                );
+                            }
+                            // Servers can't open single files
+                            RecentUrl::File(url)
+                                if self.settings.include_recent_files
+                                    && app.server_url.is_none() =>
+                            {
+                                trace!("Discovered file url {}", url);
+                                add_recent_file(
+                                    &mut workspaces,
+                                    &app.objpath,
+                                    &app.desktop_id,
+                                    url,
+                                );
+                            }
+                            RecentUrl::File(_) => {}
+                        }
+                    }
+                }
+                Err(err) => {
+                    read_all = false;
+                    if is_permission_denied(&err) {
+                        error!(
+                            "Permission denied for recent workspaces of {}: {:#}",
+                            app.desktop_id, err
+                        );
+                    } else {
+                        error!(
+                            "Failed to read recent workspaces of {}: {:#}",
+                            app.desktop_id, err
+                        );
+                    }
+                    let cached = self.cached_workspaces(&app.desktop_id);
+                    if cached.is_empty() {
+                        last_error = Some(err);
+                    } else {
+                        info!(
+                            "Keeping {} cached workspace(s) of {}",
+                            cached.len(),
+                            app.desktop_id
+                        );
+                        read_any = true;
+                        workspaces.extend(cached);
+                    }
+                }
+            }
+        }
+        remove_excluded_workspaces(&mut workspaces, &self.settings.exclude);
+        if self.settings.hide_missing_workspaces {
+            remove_missing_workspaces(&mut workspaces);
+        }
+        if self.settings.match_project_names {
+            add_projects(&mut workspaces);
+        }
+        match last_error {
+            Some(err) if !read_any => Err(err),
+            _ => {
+                info!("Found {} workspace(s)", workspaces.len());
+                // Compare in order, because the order of workspaces ranks results
+                if !self.recent_workspaces.iter().eq(workspaces.iter()) {
+                    self.recent_workspaces = workspaces;
+                    self.invalidate_snapshot();
+                }
+                // Read again on the next search if we had to fall back to cached workspaces
+                self.seen_changes = changes.filter(|_| read_all);
+                self.stamps = stamps;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The DBus interface of the search provider.
+///
+/// See <https://developer.gnome.org/SearchProvider/> for information.
+#[dbus_interface(name = "org.gnome.Shell.SearchProvider2")]
+impl VscodeSearchProvider {
+    /// Starts a search.
+    ///
+    /// This function is called when a new search is started. It gets an array of search terms as arguments,
+    /// and should return an array of result IDs. gnome-shell will call GetResultMetas for (some) of these result
+    /// IDs to get details about the result that can be be displayed in the result list.
+    #[instrument(skip(self))]
+    async fn get_initial_result_set(
+        &mut self,
+        terms: Vec<String>,
+    ) -> zbus::fdo::Result<Vec<String>> {
+        debug!("Searching for {:?}", terms);
+        self.activity.touch();
+        self.update_recent_workspaces().await.map_err(|error| {
+            error!("Failed to update recent workspaces: {:#}", error);
+            zbus::fdo::Error::Failed(format!("Failed to update recent workspaces: {:#}", error))
+        })?;
+        if self.show_placeholder() {
+            debug!("No recent workspaces, returning placeholder");
+            return Ok(vec![PLACEHOLDER_ID.to_string()]);
+        }
+        self.scores = ScoreCache::default();
+        Ok(self.search(&terms, None))
+    }
+
+    /// Refine an ongoing search.
+    ///
+    /// This function is called to refine the initial search results when the user types more characters in the search entry.
+    /// It gets the previous search results and the current search terms as arguments, and should return an array of result IDs,
+    /// just like GetInitialResultSet.
+    #[instrument(skip(self))]
+    fn get_subsearch_result_set(
+        &mut self,
+        previous_results: Vec<String>,
+        terms: Vec<String>,
+    ) -> Vec<String> {
+        debug!("Searching for {:?} in {:?}", terms, previous_results);
+        self.activity.touch();
+        if self.show_placeholder() {
+            return vec![PLACEHOLDER_ID.to_string()];
+        }
+        self.search(&terms, Some(&previous_results))
+    }
+
+    /// Get metadata for results.
+    ///
+    /// This function is called to obtain detailed information for results.
+    /// It gets an array of result IDs as arguments, and should return a matching array of dictionaries
+    /// (ie one a{sv} for each passed-in result ID).
+    #[instrument(skip(self))]
+    fn get_result_metas(&self, results: Vec<String>) -> Vec<HashMap<String, Value<'static>>> {
+        self.activity.touch();
+        results
+            .into_iter()
+            .filter_map(|id| {
+                if id == PLACEHOLDER_ID {
+                    return Some(self.placeholder_meta());
+                }
+                let workspace = self.recent_workspaces.get(&id)?;
+                trace!("Get metadata for workspace {:?}", workspace);
+                let mut meta = HashMap::new();
+                meta.insert("name".to_string(), Value::from(workspace.name.clone()));
+                if workspace.is_file {
+                    meta.insert("gicon".to_string(), Value::from(RECENT_FILE_ICON));
+                } else if let Some(icon) = self
+                    .app(&workspace.app_id)
+                    .and_then(|app| app.icon.as_ref())
+                {
+                    meta.insert("gicon".to_string(), Value::from(icon.clone()));
+                }
+                let description = self.describe(workspace);
+                meta.insert("description".to_string(), Value::from(description));
+                meta.insert("id".to_string(), Value::from(id));
+                Some(meta)
+            })
+            .collect()
+    }
+
+    /// Activate an individual result.
+    ///
+    /// This function is called when the user clicks on an individual result to open it in the application.
+    /// The arguments are the result ID, the current search terms and a timestamp.
+    ///
+    /// Launch the workspace with the app it belongs to, and then record the visit of the
+    /// workspace.  For the placeholder result just launch the app.
+    #[instrument(skip(self))]
+    async fn activate_result(
+        &self,
+        id: String,
+        terms: Vec<String>,
+        timestamp: u32,
+    ) -> zbus::fdo::Result<()> {
+        debug!("Activating {} for {:?} at {}", id, terms, timestamp);
+        self.activity.touch();
+        if id == PLACEHOLDER_ID {
+            return self.launch_first_app().await;
+        }
+        let workspace = self.recent_workspaces.get(&id).ok_or_else(|| {
+            error!("Workspace with ID {} not found", id);
+            zbus::fdo::Error::Failed(format!("Result {} not found", id))
+        })?;
+        self.launch_workspace(workspace).await?;
+        self.frecency.visit(&workspace.url, &terms);
+        Ok(())
+    }
+
+    /// Launch a search within the App.
+    ///
+    /// This function is called when the user clicks on the provider icon to display more search results in the application.
+    /// The arguments are the current search terms and a timestamp.
+    ///
+    /// We cannot search within VSCode, so we just launch the (first) app.
+    #[instrument(skip(self))]
+    async fn launch_search(&self, terms: Vec<String>, timestamp: u32) -> zbus::fdo::Result<()> {
+        self.activity.touch();
+        debug!("Launching search for {:?} at {}", terms, timestamp);
+        self.launch_first_app().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gnome_search_provider_common::app::AppLaunchService;
+    use std::fs::Permissions;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::PoisonError;
+
+    const CODE_OBJPATH: &str = "/de/swsnr/searchprovider/vscode/official/code";
+    const CODIUM_OBJPATH: &str = "/de/swsnr/searchprovider/vscode/codium";
+
+    fn workspace(app_id: &str, url: &str) -> RecentWorkspace {
+        let mut workspace = RecentWorkspace {
+            name: url.split('/').last().unwrap().to_string(),
+            url: url.to_string(),
+            description: url.trim_start_matches("file://").to_string(),
+            app_id: app_id.to_string(),
+            is_file: false,
+            path: file_url_path(url),
+            host: remote_host(url),
+            project: None,
+            language: None,
+            keys: SearchKeys::default(),
+        };
+        workspace.keys = SearchKeys::new(&workspace);
+        workspace
+    }
+
+    #[test]
+    fn update_recent_workspaces_keeps_cached_workspaces_without_permission() {
+        let _main_context = crate::tests::MAIN_CONTEXT
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let config_dir = tempfile::tempdir().unwrap();
+        let storage = config_dir.path().join("storage.json");
+        std::fs::write(&storage, "{}").unwrap();
+        std::fs::set_permissions(&storage, Permissions::from_mode(0o000)).unwrap();
+        if std::fs::File::open(&storage).is_ok() {
+            // We're root and may read any file, so there's no permission error to test
+            return;
+        }
+        let url = "file:///home/foo/dev/mdcat";
+        let mut provider = provider(ProviderSettings::default(), &[url]);
+        provider.apps = vec![ProviderApp {
+            label: "Code".to_string(),
+            desktop_id: "code.desktop".to_string(),
+            objpath: CODE_OBJPATH.to_string(),
+            icon: None,
+            config_dirs: vec![config_dir.path().to_path_buf()],
+            command: Vec::new(),
+            server_url: None,
+            changes: None,
+        }];
+        let context = glib::MainContext::default();
+        let error = context
+            .block_on(read_app_sources(&provider.apps[0], None))
+            .unwrap_err();
+        assert!(is_permission_denied(&error), "{:#}", error);
+        context
+            .block_on(provider.update_recent_workspaces())
+            .unwrap();
+        assert_eq!(
+            provider.recent_workspaces.keys().collect::<Vec<_>>(),
+            vec![url]
+        );
+    }
+
+    fn provider(settings: ProviderSettings, urls: &[&str]) -> VscodeSearchProvider {
+        let mut provider = VscodeSearchProvider::new(
+            Vec::new(),
+            AppLaunchService::new().client(),
+            ActivityTracker::default(),
+            Frecency::default(),
+            settings,
+        );
+        for url in urls {
+            provider
+                .recent_workspaces
+                .insert(url.to_string(), workspace("code.desktop", url));
+        }
+        provider
+    }
+
+    fn terms(terms: &[&str]) -> Vec<String> {
+        terms.iter().map(|term| term.to_string()).collect()
+    }
+
+    #[test]
+    fn placeholder_meta_describes_app_by_label() {
+        let mut provider = provider(ProviderSettings::default(), &[]);
+        provider.apps.push(ProviderApp {
+            label: "VSCodium".to_string(),
+            desktop_id: "codium.desktop".to_string(),
+            objpath: CODIUM_OBJPATH.to_string(),
+            icon: None,
+            config_dirs: Vec::new(),
+            command: Vec::new(),
+            server_url: None,
+            changes: None,
+        });
+        assert_eq!(
+            provider.placeholder_meta()["description"],
+            Value::from("Open VSCodium")
+        );
+    }
+
+    #[test]
+    fn add_workspace_carries_app() {
+        let mut workspaces = IndexMap::new();
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            "file:///home/foo/mdcat".into(),
+            None,
+        );
+        add_workspace(
+            &mut workspaces,
+            CODIUM_OBJPATH,
+            "codium.desktop",
+            "file:///home/foo/mdcat".into(),
+            None,
+        );
+        assert_eq!(workspaces.len(), 2);
+        assert_eq!(
+            workspaces[&workspace_id(CODE_OBJPATH, "file:///home/foo/mdcat")],
+            workspace("code.desktop", "file:///home/foo/mdcat")
+        );
+        assert_eq!(
+            workspaces[&workspace_id(CODIUM_OBJPATH, "file:///home/foo/mdcat")],
+            workspace("codium.desktop", "file:///home/foo/mdcat")
+        );
+    }
+
+    #[test]
+    fn abbreviate_home_in_path() {
+        let home = Path::new("/home/foo");
+        assert_eq!(abbreviate_home("/home/foo/dev/mdcat", home), "~/dev/mdcat");
+        assert_eq!(abbreviate_home("/home/foo", home), "~");
+        assert_eq!(
+            abbreviate_home("/home/foo/My Project", Path::new("/home/foo/")),
+            "~/My Project"
+        );
+        assert_eq!(
+            abbreviate_home("/home/foobar/dev", home),
+            "/home/foobar/dev"
+        );
+        assert_eq!(abbreviate_home("/srv/dev/mdcat", home), "/srv/dev/mdcat");
+        assert_eq!(
+            abbreviate_home("/home/foo/dev", Path::new("/")),
+            "/home/foo/dev"
+        );
+    }
+
+    #[test]
+    fn location_kind_of_urls() {
+        assert_eq!(location_kind("file:///home/foo/mdcat"), "local");
+        assert_eq!(
+            location_kind("vscode-remote://ssh-remote%2Bbuildserver/srv/app"),
+            "ssh"
+        );
+        assert_eq!(
+            location_kind("vscode-remote://tunnel+desktop/home/foo"),
+            "tunnel"
+        );
+        assert_eq!(
+            location_kind("vscode-remote://dev-container%2B2f686f6d65/workspaces/x"),
+            "dev container"
+        );
+        assert_eq!(location_kind("vscode-remote://wsl+ubuntu/home/foo"), "wsl");
+        assert_eq!(
+            location_kind("vscode-vfs://github%2B7b22/lunaryorn/mdcat"),
+            "GitHub"
+        );
+        assert_eq!(location_kind("vsls:/shared"), "vsls");
+        assert_eq!(
+            url_path("vscode-remote://wsl+ubuntu/home/foo/My%20Project"),
+            "/home/foo/My Project"
+        );
+    }
+
+    #[test]
+    fn workspace_id_is_stable() {
+        assert_eq!(
+            workspace_id(CODE_OBJPATH, "file:///home/foo/mdcat"),
+            "vscode-search-provider-15aac9c9b32e9d2a7778fd9870fe8ea4e806de429b1a443e0ee63059270dab63"
+        );
+        let mut first = IndexMap::new();
+        add_workspace(
+            &mut first,
+            CODE_OBJPATH,
+            "code.desktop",
+            "file:///home/foo/mdcat".into(),
+            None,
+        );
+        let mut second = IndexMap::new();
+        add_workspace(
+            &mut second,
+            CODE_OBJPATH,
+            "com.visualstudio.code.desktop",
+            "FILE:///home/foo/mdcat/".into(),
+            None,
+        );
+        assert_eq!(
+            first.keys().collect::<Vec<_>>(),
+            second.keys().collect::<Vec<_>>()
+        );
+        assert_ne!(
+            workspace_id(CODE_OBJPATH, "file:///home/foo/mdcat"),
+            workspace_id(CODIUM_OBJPATH, "file:///home/foo/mdcat")
+        );
+    }
+
+    #[test]
+    fn add_workspace_file_url_without_authority() {
+        let mut workspaces = IndexMap::new();
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            "file:///home/foo/x".into(),
+            None,
+        );
+        let workspace = &workspaces[&workspace_id(CODE_OBJPATH, "file:///home/foo/x")];
+        assert_eq!(workspace.name, "x");
+        assert_eq!(workspace.description, "/home/foo/x");
+        assert_eq!(workspace.url, "file:///home/foo/x");
+    }
+
+    #[test]
+    fn add_workspace_file_url_with_authority() {
+        let mut workspaces = IndexMap::new();
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            "file://localhost/home/foo/x".into(),
+            None,
+        );
+        let workspace = &workspaces[&workspace_id(CODE_OBJPATH, "file://localhost/home/foo/x")];
+        assert_eq!(workspace.name, "x");
+        assert_eq!(workspace.description, "/home/foo/x");
+        assert_eq!(workspace.url, "file://localhost/home/foo/x");
+    }
+
+    #[test]
+    fn add_workspace_file_url_with_encoded_slash() {
+        let mut workspaces = IndexMap::new();
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            "file:///home/foo/dev/a%2Fb".into(),
+            None,
+        );
+        let workspace = &workspaces[&workspace_id(CODE_OBJPATH, "file:///home/foo/dev/a%2Fb")];
+        assert_eq!(workspace.name, "a/b");
+        assert_eq!(workspace.description, "/home/foo/dev/a/b");
+    }
+
+    #[test]
+    fn add_workspace_multi_root_workspace() {
+        let mut workspaces = IndexMap::new();
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            "file:///home/foo/dev/gnome.code-workspace".into(),
+            None,
+        );
+        let workspace =
+            &workspaces[&workspace_id(CODE_OBJPATH, "file:///home/foo/dev/gnome.code-workspace")];
+        assert_eq!(workspace.name, "gnome");
+        assert_eq!(workspace.description, "/home/foo/dev/gnome.code-workspace");
+        assert_eq!(workspace.url, "file:///home/foo/dev/gnome.code-workspace");
+    }
+
+    #[test]
+    fn add_recent_file_is_file() {
+        let mut workspaces = IndexMap::new();
+        add_recent_file(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            "file:///home/foo/dev/mdcat/README.md".into(),
+        );
+        let file = &workspaces[&workspace_id(CODE_OBJPATH, "file:///home/foo/dev/mdcat/README.md")];
+        assert_eq!(file.name, "README.md");
+        assert_eq!(file.description, "/home/foo/dev/mdcat/README.md");
+        assert!(file.is_file);
+    }
+
+    #[test]
+    fn add_workspace_with_label() {
+        let mut workspaces = IndexMap::new();
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            "vscode-remote://ssh-remote%2Bfoo/home/foo/dev/mdcat".into(),
+            Some("mdcat on foo".into()),
+        );
+        let workspace = &workspaces[&workspace_id(
+            CODE_OBJPATH,
+            "vscode-remote://ssh-remote%2Bfoo/home/foo/dev/mdcat",
+        )];
+        assert_eq!(workspace.name, "mdcat on foo");
+    }
+
+    #[test]
+    fn add_workspace_ssh_remote() {
+        let mut workspaces = IndexMap::new();
+        let url = "vscode-remote://ssh-remote%2Bfoo.example.com/home/foo/dev/mdcat";
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            url.into(),
+            None,
+        );
+        let workspace = &workspaces[&workspace_id(CODE_OBJPATH, url)];
+        assert_eq!(workspace.name, "mdcat");
+        assert_eq!(
+            workspace.description,
+            "/home/foo/dev/mdcat on foo.example.com"
+        );
+        assert_eq!(workspace.host.as_deref(), Some("foo.example.com"));
+    }
+
+    #[test]
+    fn add_workspace_tunnel() {
+        let mut workspaces = IndexMap::new();
+        let url = "vscode-remote://tunnel%2Bworkstation/home/foo/dev/mdcat";
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            url.into(),
+            None,
+        );
+        let workspace = &workspaces[&workspace_id(CODE_OBJPATH, url)];
+        assert_eq!(workspace.name, "mdcat");
+        assert_eq!(
+            workspace.description,
+            "/home/foo/dev/mdcat on tunnel workstation"
+        );
+        assert_eq!(remote_cli_option(url, false), Some("--folder-uri"));
+    }
+
+    #[test]
+    fn add_workspace_codespaces() {
+        let mut workspaces = IndexMap::new();
+        let codespace = "vscode-remote://codespaces%2Bfoo-mdcat-x7j9/workspaces/mdcat";
+        let repository = "vscode-vfs://github%2B7b2276223a317d/lunaryorn/mdcat";
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            codespace.into(),
+            None,
+        );
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            repository.into(),
+            None,
+        );
+        let workspace = &workspaces[&workspace_id(CODE_OBJPATH, codespace)];
+        assert_eq!(workspace.name, "mdcat");
+        assert_eq!(
+            workspace.description,
+            "/workspaces/mdcat in codespace foo-mdcat-x7j9"
+        );
+        let workspace = &workspaces[&workspace_id(CODE_OBJPATH, repository)];
+        assert_eq!(workspace.name, "mdcat");
+        assert_eq!(workspace.description, "lunaryorn/mdcat on GitHub");
+        assert_eq!(remote_cli_option(repository, false), Some("--folder-uri"));
+    }
+
+    #[test]
+    fn add_workspace_dev_container() {
+        let mut workspaces = IndexMap::new();
+        let plain = format!(
+            "vscode-remote://dev-container%2B{}/workspaces/mdcat",
+            hex::encode("/home/foo/dev/mdcat")
+        );
+        let json = format!(
+            "vscode-remote://dev-container+{}/workspaces/gnome-shell",
+            hex::encode(r#"{"hostPath":"/home/foo/dev/gnome-shell","configFile":{"$mid":1}}"#)
+        );
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            plain.clone(),
+            None,
+        );
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            json.clone(),
+            None,
+        );
+        let workspace = &workspaces[&workspace_id(CODE_OBJPATH, &plain)];
+        assert_eq!(workspace.name, "mdcat");
+        assert_eq!(
+            workspace.description,
+            "/workspaces/mdcat in dev container for /home/foo/dev/mdcat"
+        );
+        let workspace = &workspaces[&workspace_id(CODE_OBJPATH, &json)];
+        assert_eq!(workspace.name, "gnome-shell");
+        assert_eq!(
+            workspace.description,
+            "/workspaces/gnome-shell in dev container for /home/foo/dev/gnome-shell"
+        );
+    }
+
+    #[test]
+    fn remote_cli_option_for_urls() {
+        assert_eq!(remote_cli_option("file:///home/foo/mdcat", false), None);
+        assert_eq!(
+            remote_cli_option("vscode-remote://ssh-remote%2Bfoo/home/foo/mdcat", false),
+            Some("--folder-uri")
+        );
+        assert_eq!(
+            remote_cli_option(
+                "vscode-remote://ssh-remote%2Bfoo/home/foo/x.code-workspace",
+                false
+            ),
+            Some("--file-uri")
+        );
+        assert_eq!(
+            remote_cli_option("vscode-remote://ssh-remote%2Bfoo/home/foo/README.md", true),
+            Some("--file-uri")
+        );
+    }
+
+    #[test]
+    fn server_workspace_url_for_urls() {
+        let server = "http://localhost:8080/";
+        assert_eq!(
+            server_workspace_url(server, "file:///home/foo/My%20Project", false).as_deref(),
+            Some("http://localhost:8080/?folder=%2Fhome%2Ffoo%2FMy+Project")
+        );
+        assert_eq!(
+            server_workspace_url(
+                server,
+                "vscode-remote://localhost%3A8080/home/foo/x.code-workspace",
+                false
+            )
+            .as_deref(),
+            Some("http://localhost:8080/?workspace=%2Fhome%2Ffoo%2Fx.code-workspace")
+        );
+        assert_eq!(
+            server_workspace_url(server, "file:///home/foo/README.md", true),
+            None
+        );
+        assert_eq!(
+            server_workspace_url("not a url", "file:///home/foo/mdcat", false),
+            None
+        );
+    }
+
+    #[test]
+    fn add_workspace_scheme_only_url() {
+        let mut workspaces = IndexMap::new();
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            "vscode-vfs:".into(),
+            None,
+        );
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            "vscode-vfs://github/".into(),
+            None,
+        );
+        assert_eq!(workspaces.len(), 2);
+        assert_eq!(
+            workspaces[&workspace_id(CODE_OBJPATH, "vscode-vfs:")].name,
+            "vscode-vfs:"
+        );
+        assert_eq!(
+            workspaces[&workspace_id(CODE_OBJPATH, "vscode-vfs://github/")].name,
+            "vscode-vfs://github"
+        );
+    }
+
+    #[test]
+    fn add_workspace_decodes_name_of_other_urls() {
+        let mut workspaces = IndexMap::new();
+        let url = "vscode-remote://wsl%2Bubuntu/home/foo/My%20Project";
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            url.into(),
+            None,
+        );
+        let workspace = &workspaces[&workspace_id(CODE_OBJPATH, url)];
+        assert_eq!(workspace.name, "My Project");
+        assert_eq!(
+            workspace.description,
+            "vscode-remote://wsl+ubuntu/home/foo/My Project"
+        );
+    }
+
+    #[test]
+    fn add_workspace_non_utf8_path() {
+        let mut workspaces = IndexMap::new();
+        let url = "file:///home/foo/dev/caf%E9";
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            url.into(),
+            None,
+        );
+        let workspace = &workspaces[&workspace_id(CODE_OBJPATH, url)];
+        assert_eq!(workspace.name, "caf\u{FFFD}");
+        assert_eq!(workspace.description, "/home/foo/dev/caf\u{FFFD}");
+        assert_eq!(workspace.url, url);
+        assert_eq!(
+            workspace.path,
+            Some(PathBuf::from(OsString::from_vec(
+                b"/home/foo/dev/caf\xe9".to_vec()
+            )))
+        );
+        assert!(match_score(workspace, &["caf"]) > 0.0);
+    }
+
+    #[test]
+    fn remove_excluded_workspaces_matches_path_and_url() {
+        let mut workspaces = IndexMap::new();
+        let urls = [
+            "file:///home/foo/dev/app/node_modules/left-pad",
+            "file:///home/foo/dev/mdcat",
+            "file:///tmp/scratch",
+            "vscode-remote://ssh-remote%2Bfoo/srv/app/node_modules/left-pad",
+            "vscode-remote://ssh-remote%2Bfoo/srv/app",
+        ];
+        for url in urls {
+            add_workspace(
+                &mut workspaces,
+                CODE_OBJPATH,
+                "code.desktop",
+                url.into(),
+                None,
+            );
+        }
+        let mut exclude = globset::GlobSetBuilder::new();
+        exclude.add(globset::Glob::new("**/node_modules/**").unwrap());
+        exclude.add(globset::Glob::new("/tmp/**").unwrap());
+        remove_excluded_workspaces(&mut workspaces, &exclude.build().unwrap());
+        assert_eq!(
+            workspaces
+                .values()
+                .map(|w| w.url.as_str())
+                .collect::<Vec<_>>(),
+            vec![urls[1], urls[4]]
+        );
+    }
+
+    #[test]
+    fn remove_missing_workspaces_keeps_existing_and_remote() {
+        let mut workspaces = IndexMap::new();
+        let existing = format!("file://{}", env!("CARGO_MANIFEST_DIR"));
+        let urls = [
+            existing.as_str(),
+            "file:///does/not/exist/mdcat",
+            "vscode-remote://ssh-remote%2Bfoo/does/not/exist/mdcat",
+        ];
+        for url in urls {
+            add_workspace(
+                &mut workspaces,
+                CODE_OBJPATH,
+                "code.desktop",
+                url.into(),
+                None,
+            );
+        }
+        remove_missing_workspaces(&mut workspaces);
+        assert_eq!(
+            workspaces
+                .values()
+                .map(|w| w.url.as_str())
+                .collect::<Vec<_>>(),
+            vec![urls[0], urls[2]]
+        );
+    }
+
+    #[test]
+    fn unique_workspaces_keeps_first_app() {
+        let mut workspaces = IndexMap::new();
+        for (objpath, app_id, url) in [
+            (CODE_OBJPATH, "code.desktop", "file:///home/foo/dev/mdcat"),
+            (
+                CODE_OBJPATH,
+                "code.desktop",
+                "file:///home/foo/dev/gnome-shell",
+            ),
+            (
+                CODIUM_OBJPATH,
+                "codium.desktop",
+                "file:///home/foo/dev/mdcat",
+            ),
+            (
+                CODIUM_OBJPATH,
+                "codium.desktop",
+                "file:///home/foo/dev/sbctl",
+            ),
+        ] {
+            add_workspace(&mut workspaces, objpath, app_id, url.into(), None);
+        }
+        assert_eq!(workspaces.len(), 4);
+        assert_eq!(
+            unique_workspaces(&workspaces)
+                .map(|(_, w)| (w.app_id.as_str(), w.url.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("code.desktop", "file:///home/foo/dev/mdcat"),
+                ("code.desktop", "file:///home/foo/dev/gnome-shell"),
+                ("codium.desktop", "file:///home/foo/dev/sbctl"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unique_workspaces_ignores_trailing_slash() {
+        let mut workspaces = IndexMap::new();
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            "file:///home/foo/dev/mdcat/".into(),
+            None,
+        );
+        add_workspace(
+            &mut workspaces,
+            CODIUM_OBJPATH,
+            "codium.desktop",
+            "file:///home/foo/dev/mdcat".into(),
+            None,
+        );
+        assert_eq!(
+            unique_workspaces(&workspaces)
+                .map(|(_, w)| w.app_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["code.desktop"]
+        );
+    }
+
+    #[test]
+    fn add_workspace_skips_duplicate_urls() {
+        let mut workspaces = IndexMap::new();
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            "file:///home/foo/dev/mdcat".into(),
+            Some("My mdcat".into()),
+        );
+        add_workspace(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            "file:///home/foo/dev/mdcat/".into(),
+            None,
+        );
+        add_recent_file(
+            &mut workspaces,
+            CODE_OBJPATH,
+            "code.desktop",
+            "file:///home/foo/dev/mdcat".into(),
+        );
+        assert_eq!(workspaces.len(), 1);
+        let workspace = &workspaces[&workspace_id(CODE_OBJPATH, "file:///home/foo/dev/mdcat")];
+        assert_eq!(workspace.name, "My mdcat");
+        assert_eq!(workspace.url, "file:///home/foo/dev/mdcat");
+        assert!(!workspace.is_file);
+    }
+
+    #[test]
+    fn add_workspace_ignores_query_and_fragment() {
+        let mut workspaces = IndexMap::new();
+        let urls = [
+            "file:///home/foo/dev/mdcat/?query#fragment",
+            "vscode-vfs://github/lunaryorn/mdcat?ref=main#readme",
+            "vscode-remote://wsl%2Bubuntu/home/foo/mdcat//?query",
+            "not a URL/mdcat?query#fragment",
+        ];
+        for url in urls {
+            add_workspace(
+                &mut workspaces,
+                CODE_OBJPATH,
+                "code.desktop",
+                url.into(),
+                None,
+            );
+        }
+        for url in urls {
+            assert_eq!(
+                workspaces[&workspace_id(CODE_OBJPATH, url)].name,
+                "mdcat",
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn match_score_matches_project_name() {
+        let mut workspace = workspace("code.desktop", "file:///home/foo/dev/md-renderer");
+        workspace.project = Some("mdcat".into());
+        workspace.keys = SearchKeys::new(&workspace);
+        assert_eq!(match_score(&workspace, &["mdcat"]), 8.0);
+        assert!(match_score(&workspace, &["md"]) > 8.0);
+        assert_eq!(match_score(&workspace, &["renderer", "-mdcat"]), 0.0);
+    }
+
+    #[test]
+    fn match_score_matches_remote_host() {
+        let ssh = workspace(
+            "code.desktop",
+            "vscode-remote://ssh-remote%2Bbuildserver/home/foo/dev/mdcat",
+        );
+        let container = workspace(
+            "code.desktop",
+            &format!(
+                "vscode-remote://dev-container%2B{}/workspaces/app",
+                hex::encode("/home/foo/dev/containerized")
+            ),
+        );
+        let local = workspace("code.desktop", "file:///home/foo/dev/buildserver/mdcat");
+        assert_eq!(match_score(&ssh, &["buildserver"]), 1.0);
+        assert!(match_score(&local, &["buildserver"]) < 1.0);
+        assert_eq!(match_score(&container, &["containerized"]), 1.0);
+        assert_eq!(match_score(&ssh, &["mdcat", "-buildserver"]), 0.0);
+        assert_eq!(match_score(&container, &["app", "-containerized"]), 0.0);
+    }
+
+    #[test]
+    fn match_score_decodes_url() {
+        let workspace = workspace("code.desktop", "file:///home/foo/dev/My%20Pr%C3%B6ject/src");
+        assert!(match_score(&workspace, &["my pröject"]) > 0.0);
+        assert!(match_score(&workspace, &["dev/my pröject"]) > 0.0);
+    }
+
+    #[test]
+    fn fold_words_splits_on_separators_and_camel_case() {
+        let words = |text: &str| {
+            let (folded, starts) = fold_words(text);
+            let mut ends = starts.iter().skip(1).copied().collect::<Vec<_>>();
+            ends.push(folded.len());
+            starts
+                .iter()
+                .zip(ends)
+                .map(|(start, end)| folded[*start..end].to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(words("my-test_app.rs"), vec!["my-", "test_", "app.", "rs"]);
+        assert_eq!(
+            words("GnomeSearchProvider"),
+            vec!["gnome", "search", "provider"]
+        );
+        assert_eq!(words("HTTPServer"), vec!["http", "server"]);
+        assert_eq!(words("/home/Foo"), vec!["home/", "foo"]);
+        assert_eq!(words("mdcat2"), vec!["mdcat2"]);
+        assert_eq!(words("--"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn find_matching_workspaces_prefers_word_start_matches() {
+        let mut workspaces = IndexMap::new();
+        for url in [
+            "file:///home/foo/dev/latest-notes",
+            "file:///home/foo/dev/my-test-app",
+            "file:///home/foo/dev/MyTestApp",
+        ] {
+            workspaces.insert(url.to_string(), workspace("code.desktop", url));
+        }
+        assert_eq!(
+            find_matching_workspaces(&workspaces, &["test"]),
+            vec![
+                "file:///home/foo/dev/my-test-app",
+                "file:///home/foo/dev/MyTestApp",
+                "file:///home/foo/dev/latest-notes",
+            ]
+        );
+    }
+
+    #[test]
+    fn match_score_prefers_word_start_matches_in_url() {
+        let word_start = workspace("code.desktop", "file:///home/foo/test-data/mdcat");
+        let within_word = workspace("code.desktop", "file:///home/foo/latest/mdcat");
+        assert!(match_score(&within_word, &["test"]) < match_score(&word_start, &["test"]));
+    }
+
+    #[test]
+    fn matches_acronym_of_name_components() {
+        let matches_acronym = |name: &str, term: &str| {
+            let (name, starts) = fold_words(name);
+            matches_acronym(&initials(&name, &starts), term)
+        };
+        assert!(matches_acronym("gnome-search-provider", "gsp"));
+        assert!(matches_acronym("gnome-search-provider", "gs"));
+        assert!(matches_acronym("gnome_search__provider", "gsp"));
+        assert!(matches_acronym("GnomeSearchProvider", "gsp"));
+        assert!(matches_acronym("gnome.search.provider", "gsp"));
+        assert!(!matches_acronym("gnome-search-provider", "g"));
+        assert!(!matches_acronym("gnome-search-provider", "sp"));
+        assert!(!matches_acronym("gnome-search-provider", "gspx"));
+        assert!(!matches_acronym("gnome-search-provider", "gps"));
+        assert!(!matches_acronym("mdcat", "md"));
+    }
+
+    #[test]
+    fn match_score_ranks_acronym_between_name_and_url_match() {
+        let workspace = workspace("code.desktop", "file:///home/foo/dev/gnome-search-provider");
+        let acronym = match_score(&workspace, &["gsp"]);
+        assert!(match_score(&workspace, &["dev"]) < acronym);
+        assert!(acronym < match_score(&workspace, &["search"]));
+    }
+
+    #[test]
+    fn edit_distance_counts_transpositions() {
+        assert_eq!(edit_distance("mdcat", "mdcat"), 0);
+        assert_eq!(edit_distance("mdcat", "mdact"), 1);
+        assert_eq!(edit_distance("mdcat", "mdat"), 1);
+        assert_eq!(edit_distance("mdcat", "mdccat"), 1);
+        assert_eq!(edit_distance("mdcat", "mdcot"), 1);
+        assert_eq!(edit_distance("mdcat", "dmact"), 2);
+        assert_eq!(edit_distance("pröject", "projekt"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", ""), 3);
+    }
+
+    #[test]
+    fn match_score_tolerates_typos() {
+        let workspace = workspace(
+            "code.desktop",
+            "file:///home/foo/dev/gnome-search-providers",
+        );
+        let exact = match_score(&workspace, &["search"]);
+        let typo = match_score(&workspace, &["serach"]);
+        assert!(0.0 < typo, "{}", typo);
+        assert!(typo < exact, "{} < {}", typo, exact);
+        assert!(0.0 < match_score(&workspace, &["gnome-serach-providres"]));
+        // Too many typos for the length of the term
+        assert_eq!(match_score(&workspace, &["srch"]), 0.0);
+        assert_eq!(match_score(&workspace, &["sercah"]), 0.0);
+        // Too short to tolerate typos
+        assert_eq!(match_score(&workspace, &["gmo"]), 0.0);
+    }
+
+    #[test]
+    fn match_score_excludes_negated_terms() {
+        let workspace = workspace("code.desktop", "file:///home/foo/legacy/api");
+        assert!(0.0 < match_score(&workspace, &["api", "-modern"]));
+        assert_eq!(match_score(&workspace, &["api", "-legacy"]), 0.0);
+        assert_eq!(match_score(&workspace, &["api", "-API"]), 0.0);
+        // Negated terms don't add to the score, and don't spoil the exact name bonus
+        assert_eq!(
+            match_score(&workspace, &["api", "-modern"]),
+            match_score(&workspace, &["api"])
+        );
+        // A single dash is an ordinary term
+        assert_eq!(match_score(&workspace, &["api", "-"]), 0.0);
+    }
+
+    #[test]
+    fn find_matching_workspaces_excludes_negated_terms() {
+        let mut workspaces = IndexMap::new();
+        for url in [
+            "file:///home/foo/dev/api",
+            "file:///home/foo/dev/api-legacy",
+            "file:///home/foo/legacy/api",
+        ] {
+            workspaces.insert(url.to_string(), workspace("code.desktop", url));
+        }
+        assert_eq!(
+            find_matching_workspaces(&workspaces, &["api", "-legacy"]),
+            vec!["file:///home/foo/dev/api"]
+        );
+    }
+
+    #[test]
+    fn match_score_matches_any_optional_term() {
+        let workspace = workspace("code.desktop", "file:///home/foo/dev/mdcat");
+        assert!(0.0 < match_score(&workspace, &["~mdcat", "~other"]));
+        assert_eq!(match_score(&workspace, &["~other", "~unknown"]), 0.0);
+        // Optional terms add to the score if they match
+        assert!(
+            match_score(&workspace, &["~dev", "~other"])
+                < match_score(&workspace, &["~dev", "~mdcat"])
+        );
+        assert_eq!(
+            match_score(&workspace, &["~mdcat"]),
+            match_score(&workspace, &["mdcat"])
+        );
+        // Required terms still need to match
+        assert_eq!(match_score(&workspace, &["other", "~mdcat"]), 0.0);
+        // A single tilde is an ordinary term
+        assert_eq!(match_score(&workspace, &["mdcat", "~"]), 0.0);
+    }
+
+    #[test]
+    fn find_matching_workspaces_matches_any_optional_term() {
+        let mut workspaces = IndexMap::new();
+        for url in [
+            "file:///home/foo/dev/mdcat",
+            "file:///home/foo/dev/other",
+            "file:///home/foo/dev/api",
+        ] {
+            workspaces.insert(url.to_string(), workspace("code.desktop", url));
+        }
+        assert_eq!(
+            find_matching_workspaces(&workspaces, &["~mdcat", "~api"]),
+            vec!["file:///home/foo/dev/mdcat", "file:///home/foo/dev/api"]
+        );
+    }
+
+    #[test]
+    fn segment_score_prefers_last_segment() {
+        let url = "file:///home/foo/dev/mdcat";
+        assert_eq!(segment_score(url, url.len()), 1.0);
+        assert_eq!(segment_score(url, url.find("dev").unwrap() + 3), 0.5);
+        assert_eq!(segment_score(url, url.find("foo").unwrap() + 3), 1.0 / 3.0);
+        let url = "vscode-remote://ssh-remote+foo/home/foo/dev/mdcat//";
+        assert_eq!(segment_score(url, url.find("mdcat").unwrap() + 5), 1.0);
+    }
+
+    #[test]
+    fn match_score_ranks_url_matches_by_segment() {
+        let long = workspace(
+            "code.desktop",
+            "file:///home/foo/dev/a-very-long-directory-name/mdcat",
+        );
+        let short = workspace("code.desktop", "file:///home/foo/dev/mdcat");
+        // "dev" is the parent in the short URL and the grandparent in the long URL,
+        // regardless of its position in the URL
+        assert!(match_score(&long, &["dev"]) < match_score(&short, &["dev"]));
+        assert_eq!(
+            match_score(&short, &["dev"]),
+            match_score(&workspace("code.desktop", "file:///d/dev/mdcat"), &["dev"])
+        );
+    }
+
+    #[test]
+    fn match_score_requires_all_terms() {
+        let workspace = workspace("code.desktop", "file:///home/foo/dev/mdcat");
+        assert!(match_score(&workspace, &["mdcat", "foo"]) > 0.0);
+        assert_eq!(match_score(&workspace, &["mdcat", "bar"]), 0.0);
+    }
+
+    #[test]
+    fn score_cache_incremental_matches_full_scoring() {
+        let mut workspaces = IndexMap::new();
+        for url in [
+            "file:///home/foo/dev/mdcat",
+            "file:///home/foo/dev/mdcat-fork",
+            "file:///home/foo/mdcat/dev",
+            "file:///home/bar/dev/cat",
+            "vscode-remote://ssh-remote+foo/home/foo/dev/mdcat",
+        ] {
+            workspaces.insert(url.to_string(), workspace("code.desktop", url));
+        }
+        for searches in [
+            vec![
+                vec!["cat"],
+                vec!["cat", "dev"],
+                vec!["cat", "dev", "foo"],
+                vec!["mdcat", "dev"],
+                vec!["mdcat", "dev", "home/dev"],
+            ],
+            // Only negated terms, or optional terms which don't match, match workspaces
+            // without scoring them
+            vec![vec!["-fork"], vec!["-fork", "mdcat"]],
+            vec![vec!["~other"], vec!["~other", "mdcat"]],
+        ] {
+            let mut cache = ScoreCache::default();
+            let mut previous: Vec<String> = workspaces.keys().cloned().collect();
+            for terms in searches {
+                // Narrow each search to the previous results, like subsearches do,
+                // unless the previous search left out workspaces
+                let candidates = workspaces
+                    .iter()
+                    .filter(|(id, _)| cache.dropped || previous.contains(id))
+                    .collect::<Vec<_>>();
+                let incremental = cache.find_matching_workspaces(
+                    candidates.clone(),
+                    &terms,
+                    0.0,
+                    &HashMap::new(),
+                    &HashSet::new(),
+                );
+                assert_eq!(
+                    incremental,
+                    find_matching_workspaces(candidates, &terms),
+                    "{:?}",
+                    terms
+                );
+                previous = incremental;
+            }
+            assert!(!previous.is_empty());
+        }
+    }
+
+    #[test]
+    fn subsearch_finds_weak_matches_of_previous_search() {
+        let settings = ProviderSettings {
+            min_score: 1.0,
+            ..ProviderSettings::default()
+        };
+        let mut provider = provider(
+            settings,
+            &["file:///home/foo/dev/mdcat", "file:///home/foo/other"],
+        );
+        // A match in the parent segment only scores 0.75
+        let previous = provider.search(&terms(&["dev"]), None);
+        assert!(previous.is_empty());
+        assert_eq!(
+            provider.search(&terms(&["dev", "mdcat"]), Some(&previous)),
+            vec!["file:///home/foo/dev/mdcat"]
+        );
+    }
+
+    #[test]
+    fn subsearch_after_only_negated_terms() {
+        let mut provider = provider(
+            ProviderSettings::default(),
+            &[
+                "file:///home/foo/dev/mdcat",
+                "file:///home/foo/dev/mdcat-fork",
+            ],
+        );
+        let previous = provider.search(&terms(&["-fork"]), None);
+        assert!(previous.is_empty());
+        assert_eq!(
+            provider.search(&terms(&["-fork", "mdcat"]), Some(&previous)),
+            vec!["file:///home/foo/dev/mdcat"]
+        );
+    }
+
+    #[test]
+    fn subsearch_after_unmatched_optional_terms() {
+        let mut provider = provider(
+            ProviderSettings::default(),
+            &["file:///home/foo/dev/mdcat", "file:///home/foo/dev/api"],
+        );
+        let previous = provider.search(&terms(&["~other"]), None);
+        assert!(previous.is_empty());
+        assert_eq!(
+            provider.search(&terms(&["~other", "mdcat"]), Some(&previous)),
+            vec!["file:///home/foo/dev/mdcat"]
+        );
+    }
+
+    #[test]
+    fn score_cache_ranks_frecent_workspaces_first() {
+        let mut workspaces = IndexMap::new();
+        for url in ["file:///home/foo/mdcat", "file:///home/bar/mdcat"] {
+            workspaces.insert(url.to_string(), workspace("code.desktop", url));
+        }
+        let mut bonuses = HashMap::new();
+        bonuses.insert("file:///home/bar/mdcat".to_string(), 1.0);
+        assert_eq!(
+            ScoreCache::default().find_matching_workspaces(
+                &workspaces,
+                &["mdcat"],
+                0.0,
+                &bonuses,
+                &HashSet::new()
+            ),
+            vec!["file:///home/bar/mdcat", "file:///home/foo/mdcat"]
+        );
+        // Bonuses don't make workspaces match
+        assert!(ScoreCache::default()
+            .find_matching_workspaces(&workspaces, &["other"], 0.0, &bonuses, &HashSet::new())
+            .is_empty());
+    }
+
+    #[test]
+    fn score_cache_discards_weak_matches() {
+        let mut workspaces = IndexMap::new();
+        for url in ["file:///home/foo/mdcat", "file:///home/foo/dev/other"] {
+            workspaces.insert(url.to_string(), workspace("code.desktop", url));
+        }
+        let mut cache = ScoreCache::default();
+        assert_eq!(
+            cache.find_matching_workspaces(
+                &workspaces,
+                &["foo"],
+                0.0,
+                &HashMap::new(),
+                &HashSet::new()
+            ),
+            vec!["file:///home/foo/mdcat", "file:///home/foo/dev/other"]
+        );
+        let mut bonuses = HashMap::new();
+        bonuses.insert("file:///home/foo/dev/other".to_string(), 2.0);
+        let mut cache = ScoreCache::default();
+        assert!(cache
+            .find_matching_workspaces(&workspaces, &["foo"], 1.0, &bonuses, &HashSet::new())
+            .is_empty());
+        // A weak match of one term still matches if more terms match strongly
+        assert_eq!(
+            cache.find_matching_workspaces(
+                &workspaces,
+                &["foo", "mdcat"],
+                1.0,
+                &bonuses,
+                &HashSet::new()
+            ),
+            vec!["file:///home/foo/mdcat"]
+        );
+    }
+
+    #[test]
+    fn parse_filters_splits_filters_from_terms() {
+        let (filters, terms) = parse_filters(
+            [
+                "remote:ssh",
+                "mdcat",
+                "file:",
+                "lang:rs",
+                "file:///home",
+                "lang:",
+            ]
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
+        );
+        assert_eq!(
+            filters,
+            vec![
+                Filter::Remote("ssh".into()),
+                Filter::File,
+                Filter::Lang("rust".into()),
+                Filter::Lang("".into()),
+            ]
+        );
+        assert_eq!(terms, vec!["mdcat", "file:///home"]);
+    }
+
+    #[test]
+    fn score_cache_applies_filters() {
+        let mut workspaces = IndexMap::new();
+        let mut rust = workspace("code.desktop", "file:///home/foo/dev/mdcat");
+        rust.language = Some("rust");
+        let mut file = workspace("code.desktop", "file:///home/foo/dev/mdcat/README.md");
+        file.is_file = true;
+        for workspace in [
+            rust,
+            file,
+            workspace(
+                "code.desktop",
+                "vscode-remote://ssh-remote%2Bbuildserver/home/foo/dev/mdcat",
+            ),
+            workspace(
+                "code.desktop",
+                "vscode-remote://tunnel%2Bdesktop/home/foo/mdcat",
+            ),
+        ] {
+            workspaces.insert(workspace.url.clone(), workspace);
+        }
+        let mut cache = ScoreCache::default();
+        let mut search = |terms: &[&str]| {
+            cache.find_matching_workspaces(
+                &workspaces,
+                terms,
+                0.0,
+                &HashMap::new(),
+                &HashSet::new(),
+            )
+        };
+        assert_eq!(search(&["mdcat"]).len(), 4);
+        assert_eq!(
+            search(&["mdcat", "lang:rust"]),
+            vec!["file:///home/foo/dev/mdcat"]
+        );
+        assert_eq!(
+            search(&["mdcat", "file:"]),
+            vec!["file:///home/foo/dev/mdcat/README.md"]
+        );
+        assert_eq!(
+            search(&["remote:"]),
+            vec![
+                "vscode-remote://ssh-remote%2Bbuildserver/home/foo/dev/mdcat",
+                "vscode-remote://tunnel%2Bdesktop/home/foo/mdcat"
+            ]
+        );
+        assert_eq!(
+            search(&["remote:ssh", "mdcat"]),
+            vec!["vscode-remote://ssh-remote%2Bbuildserver/home/foo/dev/mdcat"]
+        );
+        // Changing a filter doesn't reuse scores of the previous search
+        assert_eq!(
+            search(&["remote:desktop", "mdcat"]),
+            vec!["vscode-remote://tunnel%2Bdesktop/home/foo/mdcat"]
+        );
+        assert!(search(&["lang:python"]).is_empty());
+    }
+
+    #[test]
+    fn score_cache_returns_recent_workspaces_for_single_character() {
+        let mut workspaces = IndexMap::new();
+        for url in [
+            "file:///home/foo/dev/mdcat",
+            "file:///home/foo/dev/gnome-search-providers-vscode",
+            "file:///home/foo/dev/ansible-config",
+        ] {
+            workspaces.insert(url.to_string(), workspace("code.desktop", url));
+        }
+        let mut bonuses = HashMap::new();
+        bonuses.insert("file:///home/foo/dev/ansible-config".to_string(), 10.0);
+        let mut cache = ScoreCache::default();
+        let mut search = |terms: &[&str]| {
+            cache.find_matching_workspaces(&workspaces, terms, 5.0, &bonuses, &HashSet::new())
+        };
+        // A single character returns all workspaces, most recent first, regardless of
+        // min_score and bonuses
+        assert_eq!(
+            search(&["m"]),
+            vec![
+                "file:///home/foo/dev/mdcat",
+                "file:///home/foo/dev/gnome-search-providers-vscode",
+                "file:///home/foo/dev/ansible-config",
+            ]
+        );
+        // More characters score workspaces again
+        assert_eq!(search(&["md"]), vec!["file:///home/foo/dev/mdcat"]);
+        assert_eq!(search(&["m"]).len(), 3);
+        assert_eq!(
+            search(&["m", "ansible"]),
+            vec!["file:///home/foo/dev/ansible-config"]
+        );
+    }
+
+    #[test]
+    fn score_cache_ranks_pinned_workspaces_first() {
+        let mut workspaces = IndexMap::new();
+        for url in [
+            "file:///home/foo/dev/mdcat",
+            "file:///home/foo/dev/other",
+            "file:///home/foo/dev/pinned",
+        ] {
+            workspaces.insert(url.to_string(), workspace("code.desktop", url));
+        }
+        let mut pinned = HashSet::new();
+        pinned.insert("file:///home/foo/dev/pinned".to_string());
+        assert_eq!(
+            ScoreCache::default().find_matching_workspaces(
+                &workspaces,
+                &["dev"],
+                0.0,
+                &HashMap::new(),
+                &pinned
+            ),
+            vec![
+                "file:///home/foo/dev/pinned",
+                "file:///home/foo/dev/mdcat",
+                "file:///home/foo/dev/other"
+            ]
+        );
+        // Pinned workspaces match even if they score less than the minimum score
+        assert_eq!(
+            ScoreCache::default().find_matching_workspaces(
+                &workspaces,
+                &["dev"],
+                5.0,
+                &HashMap::new(),
+                &pinned
+            ),
+            vec!["file:///home/foo/dev/pinned"]
+        );
+        // But only if they match at all
+        assert!(ScoreCache::default()
+            .find_matching_workspaces(&workspaces, &["mdcat"], 0.0, &HashMap::new(), &pinned)
+            .iter()
+            .all(|id| id != "file:///home/foo/dev/pinned"));
+    }
+
+    #[test]
+    fn join_phrases_joins_quoted_terms() {
+        assert_eq!(
+            join_phrases(&["\"my", "project\"", "api"]),
+            vec!["my project", "api"]
+        );
+        assert_eq!(
+            join_phrases(&["-\"legacy", "api\"", "~\"a", "b\"", "\"single\""]),
+            vec!["-legacy api", "~a b", "single"]
+        );
+        // Unclosed phrases extend to the last term
+        assert_eq!(join_phrases(&["api", "\"my", "pro"]), vec!["api", "my pro"]);
+        for empty in ["\"", "\"\"", "-\""] {
+            assert_eq!(join_phrases(&[empty]), Vec::<String>::new(), "{}", empty);
+        }
+        assert_eq!(join_phrases(&["\"", "mdcat"]), vec!["mdcat"]);
+        assert_eq!(join_phrases(&["foo\"bar"]), vec!["foo\"bar"]);
+    }
+
+    #[test]
+    fn match_score_transliterates_name() {
+        let cyrillic = workspace("code.desktop", "file:///home/foo/dev/Проект");
+        assert_eq!(match_score(&cyrillic, &["proekt"]), 9.0);
+        assert_eq!(match_score(&cyrillic, &["проект"]), 12.0 + EXACT_NAME_BONUS);
+        assert_eq!(match_score(&cyrillic, &["dev", "-proekt"]), 0.0);
+        // Don't transliterate ASCII names
+        let ascii = workspace("code.desktop", "file:///home/foo/dev/mdcat");
+        assert_eq!(match_score(&ascii, &["proekt"]), 0.0);
+    }
+
+    #[test]
+    fn match_score_requires_contiguous_phrase() {
+        let phrase = workspace("code.desktop", "file:///home/foo/dev/My%20Project");
+        assert!(match_score(&phrase, &["\"my", "project\""]) > 0.0);
+        let apart = workspace("code.desktop", "file:///home/foo/my/dev/project");
+        assert!(match_score(&apart, &["my", "project"]) > 0.0);
+        assert_eq!(match_score(&apart, &["\"my", "project\""]), 0.0);
+    }
+
+    #[test]
+    fn fold_terms_caps_term_length() {
+        let terms = fold_terms(&["Ä".repeat(MAX_TERM_LENGTH + 10), "Foo".to_string()]);
+        assert_eq!(terms, vec!["a".repeat(MAX_TERM_LENGTH), "foo".to_string()]);
+    }
+
+    #[test]
+    fn fold_strips_accents_and_folds_case() {
+        assert_eq!(fold("Über-App"), "uber-app");
+        assert_eq!(fold("Straße"), "strasse");
+        assert_eq!(fold("ΣΊΣΥΦΟΣ"), fold("σίσυφος"));
+        assert_eq!(fold("ﬁle Café"), "file cafe");
+        assert_eq!(fold("e\u{301}"), "e");
+    }
+
+    #[test]
+    fn match_score_ignores_case_and_accents() {
+        let accents = workspace("code.desktop", "file:///home/foo/dev/Über-App");
+        assert!(0.0 < match_score(&accents, &["uber"]));
+        assert!(0.0 < match_score(&accents, &["ÜBER"]));
+        let encoded = workspace("code.desktop", "file:///home/foo/%C3%9Cber/app");
+        assert!(0.0 < match_score(&encoded, &["uber/app"]));
+        let plain = workspace("code.desktop", "file:///home/foo/dev/uber-app");
+        assert!(0.0 < match_score(&plain, &["Über"]));
+    }
+
+    #[test]
+    fn find_matching_workspaces_long_terms_in_large_history() {
+        let mut workspaces = IndexMap::new();
+        for i in 0..2000 {
+            let url = format!("file:///home/foo/{}/{}", "a".repeat(200), i);
+            workspaces.insert(url.clone(), workspace("code.desktop", &url));
+        }
+        let terms = vec!["a".repeat(100_000) + "b", "/a".repeat(50_000)];
+        let start = std::time::Instant::now();
+        assert!(find_matching_workspaces(&workspaces, &terms).is_empty());
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn find_matching_workspaces_ranks_exact_name_first() {
+        let mut workspaces = IndexMap::new();
+        workspaces.insert(
+            "fork".to_string(),
+            workspace("code.desktop", "file:///home/foo/dev/mdcat-fork"),
+        );
+        workspaces.insert(
+            "exact".to_string(),
+            workspace("code.desktop", "file:///home/foo/dev/mdcat"),
+        );
+        assert_eq!(
+            find_matching_workspaces(&workspaces, &["mdcat"]),
+            vec!["exact", "fork"]
+        );
+        assert_eq!(
+            find_matching_workspaces(&workspaces, &["MDCat"]),
+            vec!["exact", "fork"]
+        );
+    }
+
+    #[test]
+    fn find_matching_workspaces_ranks_name_prefix_above_word_start() {
+        let mut workspaces = IndexMap::new();
+        for (id, url) in [
+            ("word", "file:///home/foo/dev/my-mdcat"),
+            ("prefix", "file:///home/foo/dev/mdcat-fork"),
+            ("exact", "file:///home/foo/dev/mdcat"),
+            ("within", "file:///home/foo/dev/catmdcat"),
+        ] {
+            workspaces.insert(id.to_string(), workspace("code.desktop", url));
+        }
+        assert_eq!(
+            find_matching_workspaces(&workspaces, &["mdcat"]),
+            vec!["exact", "prefix", "word", "within"]
+        );
+    }
+
+    #[test]
+    fn match_score_adds_name_bonuses() {
+        let exact = workspace("code.desktop", "file:///home/foo/dev/mdcat");
+        let prefix = workspace("code.desktop", "file:///home/foo/dev/mdcat-fork");
+        // "mdcat" also matches at the start of a word, unlike "cat"
+        assert_eq!(
+            match_score(&exact, &["mdcat"]),
+            match_score(&exact, &["cat"]) + EXACT_NAME_BONUS + 2.0
+        );
+        assert_eq!(
+            match_score(&prefix, &["mdcat"]),
+            match_score(&prefix, &["fork"]) + NAME_PREFIX_BONUS
+        );
+        // No bonus for a mere URL match
+        assert!(match_score(&exact, &["dev"]) < 1.0);
+    }
+
+    #[test]
+    fn rank_workspaces_orders_deterministically() {
+        let mut workspaces = IndexMap::new();
+        for (id, app_id) in [
+            ("code-1", "code.desktop"),
+            ("codium-1", "codium.desktop"),
+            ("nan", "code.desktop"),
+            ("code-2", "code.desktop"),
+            ("best", "codium.desktop"),
+            ("codium-2", "codium.desktop"),
+        ] {
+            workspaces.insert(
+                id.to_string(),
+                workspace(app_id, &format!("file:///home/foo/{}", id)),
+            );
+        }
+        let ranked = rank_workspaces(&workspaces, |id, _| match id.as_str() {
+            "nan" => f64::NAN,
+            "best" => 2.0,
+            _ => 1.0,
+        });
+        assert_eq!(
+            ranked,
+            vec!["best", "code-1", "code-2", "codium-1", "codium-2"]
+        );
+    }
+
+    #[test]
+    fn rank_workspaces_prefers_shallow_paths_on_equal_score() {
+        let mut workspaces = IndexMap::new();
+        for (id, app_id, url) in [
+            (
+                "package",
+                "code.desktop",
+                "file:///home/foo/dev/monorepo/packages/app",
+            ),
+            ("root", "codium.desktop", "file:///home/foo/dev/app"),
+            (
+                "remote",
+                "codium.desktop",
+                "vscode-remote://ssh-remote%2Bfoo/app",
+            ),
+        ] {
+            workspaces.insert(id.to_string(), workspace(app_id, url));
+        }
+        let ranked = rank_workspaces(&workspaces, |_, _| 1.0);
+        assert_eq!(ranked, vec!["remote", "root", "package"]);
+    }
+
+    #[test]
+    fn rank_workspaces_prefers_shallow_paths_within_an_app() {
+        let mut workspaces = IndexMap::new();
+        for (id, url) in [
+            ("package", "file:///home/foo/dev/monorepo/packages/app"),
+            ("root", "file:///home/foo/dev/monorepo"),
+            ("old-root", "file:///home/bar/dev/monorepo"),
+        ] {
+            workspaces.insert(id.to_string(), workspace("code.desktop", url));
+        }
+        let ranked = rank_workspaces(&workspaces, |_, _| 1.0);
+        // Shallower paths first, and then more recent workspaces
+        assert_eq!(ranked, vec!["root", "old-root", "package"]);
+    }
+
+    #[test]
+    fn path_depth_counts_path_segments() {
+        assert_eq!(path_depth("file:///home/foo/dev/mdcat/"), 4);
+        assert_eq!(path_depth("file://localhost/home/foo"), 2);
+        assert_eq!(path_depth("vscode-remote://ssh-remote%2Bfoo/home"), 1);
+        assert_eq!(path_depth("vscode-vfs://github"), 0);
+        assert_eq!(path_depth("not a URL/mdcat"), 2);
+    }
+
+    #[test]
+    fn find_matching_workspaces_path_term() {
+        let mut workspaces = IndexMap::new();
+        workspaces.insert(
+            "other".to_string(),
+            workspace("code.desktop", "file:///home/foo/mdcat"),
+        );
+        workspaces.insert(
+            "reversed".to_string(),
+            workspace("code.desktop", "file:///home/foo/mdcat/dev"),
+        );
+        workspaces.insert(
+            "dev".to_string(),
+            workspace("code.desktop", "file:///home/foo/dev/mdcat"),
+        );
+        assert_eq!(
+            find_matching_workspaces(&workspaces, &["dev/mdcat"]),
+            vec!["dev"]
+        );
+        assert_eq!(
+            find_matching_workspaces(&workspaces, &["foo/mdcat"]),
+            vec!["other", "dev", "reversed"]
+        );
+    }
+
+    #[test]
+    fn match_score_prefers_ordered_path_match_over_url_match() {
+        let workspace = workspace("code.desktop", "file:///home/foo/dev/mdcat");
+        assert!(match_score(&workspace, &["home"]) < match_score(&workspace, &["home/dev"]));
+        assert!(match_score(&workspace, &["home/dev"]) < match_score(&workspace, &["mdcat"]));
+    }
+
+    #[test]
+    fn find_matching_workspaces_keeps_apps_together_on_equal_score() {
+        let mut workspaces = IndexMap::new();
+        for (id, app_id, url) in [
+            ("code-1", "code.desktop", "file:///home/foo/mdcat"),
+            ("codium-1", "codium.desktop", "file:///home/foo/mdcat"),
+            ("code-2", "code.desktop", "file:///home/bar/mdcat"),
+            ("codium-2", "codium.desktop", "file:///home/bar/mdcat"),
+        ] {
+            workspaces.insert(id.to_string(), workspace(app_id, url));
+        }
+        assert_eq!(
+            find_matching_workspaces(&workspaces, &["mdcat"]),
+            vec!["code-1", "code-2", "codium-1", "codium-2"]
+        );
+    }
+
+    #[test]
+    fn find_matching_workspaces_ranks_recent_workspaces_first() {
+        let mut workspaces = IndexMap::new();
+        for (id, url) in [
+            ("recent", "file:///home/foo/mdcat-fork"),
+            ("other", "file:///home/foo/dev/other"),
+            ("old", "file:///home/bar/mdcat-fork"),
+            ("old-exact", "file:///home/bar/mdcat"),
+        ] {
+            workspaces.insert(id.to_string(), workspace("code.desktop", url));
+        }
+        assert_eq!(
+            find_matching_workspaces(&workspaces, &["mdcat"]),
+            vec!["old-exact", "recent", "old"]
+        );
+    }
+
+    #[test]
+    fn find_matching_workspaces_ranks_name_over_url() {
+        let mut workspaces = IndexMap::new();
+        workspaces.insert(
+            "path".to_string(),
+            workspace("code.desktop", "file:///home/foo/mdcat/sources"),
+        );
+        workspaces.insert(
+            "name".to_string(),
+            workspace("codium.desktop", "file:///home/foo/mdcat"),
+        );
+        assert_eq!(
+            find_matching_workspaces(&workspaces, &["mdcat"]),
+            vec!["name", "path"]
+        );
+    }
+}
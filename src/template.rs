@@ -0,0 +1,196 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Templates for the descriptions of search results.
+
+use anyhow::{anyhow, Result};
+
+/// A value to insert into a description.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    /// `{path}`, the path of the workspace.
+    Path,
+    /// `{host}`, the remote host of the workspace.
+    Host,
+    /// `{kind}`, where the workspace is, e.g. `local` or `ssh`.
+    Kind,
+    /// `{last_opened}`, when the workspace was last opened from search.
+    LastOpened,
+    /// `{app}`, the label of the app the workspace belongs to.
+    App,
+}
+
+impl Placeholder {
+    /// Get the placeholder with the given `name`.
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "path" => Some(Self::Path),
+            "host" => Some(Self::Host),
+            "kind" => Some(Self::Kind),
+            "last_opened" => Some(Self::LastOpened),
+            "app" => Some(Self::App),
+            _ => None,
+        }
+    }
+}
+
+/// A part of a description template.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    /// Literal text.
+    Text(String),
+    /// A value to insert.
+    Placeholder(Placeholder),
+}
+
+/// A template for the description of search results.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DescriptionTemplate {
+    segments: Vec<Segment>,
+}
+
+impl DescriptionTemplate {
+    /// Parse a description `template`.
+    ///
+    /// Replace `{path}`, `{host}`, `{kind}`, `{last_opened}` and `{app}` with the
+    /// corresponding values, and `{{` and `}}` with literal braces.  Fail on any other
+    /// placeholder, and on unbalanced braces.
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| anyhow!("Unclosed placeholder in {:?}", template))?;
+                    let name = &rest[..end];
+                    let placeholder = Placeholder::from_name(name).ok_or_else(|| {
+                        anyhow!("Unknown placeholder {{{}}} in {:?}", name, template)
+                    })?;
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Segment::Placeholder(placeholder));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(anyhow!("Unmatched }} in {:?}", template)),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Self { segments })
+    }
+
+    /// Render this template with the values of placeholders from `value`.
+    ///
+    /// Render placeholders without value, e.g. `{host}` of local workspaces, as empty
+    /// text, but keep the text around them, e.g. `{path} on {host}` renders as `~/dev on`.
+    /// Trim whitespace around the result, for placeholders without value at the start
+    /// or the end of the template.
+    pub fn render<F>(&self, mut value: F) -> String
+    where
+        F: FnMut(Placeholder) -> String,
+    {
+        let mut description = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => description.push_str(text),
+                Segment::Placeholder(placeholder) => description.push_str(&value(*placeholder)),
+            }
+        }
+        description.trim().to_string()
+    }
+}
+
+/// Describe an age of `seconds` for humans, e.g. `3 days ago`.
+pub fn format_age(seconds: u64) -> String {
+    const DAY: u64 = 24 * 60 * 60;
+    let plural = |count: u64, unit: &str| {
+        if count == 1 {
+            format!("1 {} ago", unit)
+        } else {
+            format!("{} {}s ago", count, unit)
+        }
+    };
+    match seconds / DAY {
+        0 => "today".to_string(),
+        1 => "yesterday".to_string(),
+        days @ 2..=13 => plural(days, "day"),
+        days @ 14..=59 => plural(days / 7, "week"),
+        days @ 60..=364 => plural(days / 30, "month"),
+        days => plural(days / 365, "year"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_render_template() {
+        let template =
+            DescriptionTemplate::parse("{path} on {host} ({kind}), {{{last_opened}}}").unwrap();
+        assert_eq!(
+            template.render(|placeholder| format!("{:?}", placeholder)),
+            "Path on Host (Kind), {LastOpened}"
+        );
+        let template = DescriptionTemplate::parse("{last_opened} {path}").unwrap();
+        assert_eq!(
+            template.render(|placeholder| match placeholder {
+                Placeholder::Path => "~/dev/mdcat".to_string(),
+                _ => String::new(),
+            }),
+            "~/dev/mdcat"
+        );
+        let template = DescriptionTemplate::parse("{app}: {path} on {host}").unwrap();
+        assert_eq!(
+            template.render(|placeholder| match placeholder {
+                Placeholder::Path => "~/dev".to_string(),
+                Placeholder::App => "VSCodium".to_string(),
+                _ => String::new(),
+            }),
+            "VSCodium: ~/dev on"
+        );
+    }
+
+    #[test]
+    fn parse_rejects_invalid_templates() {
+        assert!(DescriptionTemplate::parse("{name}").is_err());
+        assert!(DescriptionTemplate::parse("{path").is_err());
+        assert!(DescriptionTemplate::parse("path}").is_err());
+        assert_eq!(
+            DescriptionTemplate::parse("")
+                .unwrap()
+                .render(|_| unreachable!()),
+            ""
+        );
+    }
+
+    #[test]
+    fn format_age_in_largest_unit() {
+        const DAY: u64 = 24 * 60 * 60;
+        assert_eq!(format_age(60), "today");
+        assert_eq!(format_age(DAY + 1), "yesterday");
+        assert_eq!(format_age(5 * DAY), "5 days ago");
+        assert_eq!(format_age(15 * DAY), "2 weeks ago");
+        assert_eq!(format_age(90 * DAY), "3 months ago");
+        assert_eq!(format_age(400 * DAY), "1 year ago");
+        assert_eq!(format_age(1000 * DAY), "2 years ago");
+    }
+}